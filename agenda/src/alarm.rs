@@ -0,0 +1,63 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{Duration, TimeZone};
+use icalendar::Alarm;
+use uuid::Uuid;
+
+use crate::{LessonLike, Render};
+
+/// Which exported lessons get a reminder (`VALARM`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AlarmPolicy {
+    #[default]
+    None,
+    /// Remind `before` the start of every lesson.
+    EveryLesson { before: Duration },
+    /// Remind `before` the start of the first lesson of each day.
+    FirstOfDay { before: Duration },
+}
+
+impl AlarmPolicy {
+    /// Decide which of `lessons` should have an alarm, and how long before
+    /// they start. Days are split in the time zone `tz`.
+    pub fn plan<T: LessonLike, Tz: TimeZone>(&self, lessons: &[T], tz: &Tz) -> Alarms {
+        match *self {
+            AlarmPolicy::None => Alarms::None,
+            AlarmPolicy::EveryLesson { before } => Alarms::All(before),
+            AlarmPolicy::FirstOfDay { before } => {
+                let mut first = BTreeMap::new();
+
+                for lesson in lessons {
+                    let date = lesson.start().with_timezone(tz).date_naive();
+                    let entry = first.entry(date).or_insert(lesson);
+                    if lesson.start() < entry.start() {
+                        *entry = lesson;
+                    }
+                }
+
+                Alarms::Some(first.into_values().map(|l| l.id()).collect(), before)
+            }
+        }
+    }
+}
+
+/// Result of [`AlarmPolicy::plan`].
+#[derive(Debug, Clone)]
+pub enum Alarms {
+    None,
+    All(Duration),
+    Some(HashSet<Uuid>, Duration),
+}
+
+impl Alarms {
+    pub fn get<T: LessonLike>(&self, lesson: &T, render: &Render) -> Option<Alarm> {
+        let before = match self {
+            Alarms::None => return None,
+            Alarms::All(before) => *before,
+            Alarms::Some(ids, before) if ids.contains(&lesson.id()) => *before,
+            Alarms::Some(..) => return None,
+        };
+
+        Some(Alarm::display(&render.summary(lesson), -before))
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::LessonLike;
+
+/// A set of lessons that overlap each other, directly or through other
+/// lessons in the set.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict<'a, T> {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Number of side-by-side columns needed to draw the set.
+    pub columns: usize,
+    pub lessons: Vec<Placement<'a, T>>,
+}
+
+/// A lesson in a [`Conflict`] and the column it can be drawn in without
+/// overlapping any other lesson in that column.
+#[derive(Debug, Clone, Serialize)]
+pub struct Placement<'a, T> {
+    pub lesson: &'a T,
+    pub column: usize,
+}
+
+/// Find all sets of overlapping lessons. Lessons that don't overlap anything
+/// are left out.
+pub fn conflicts<T: LessonLike>(lessons: &[T]) -> Vec<Conflict<'_, T>> {
+    let mut sorted = lessons.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|l| (l.start(), l.end()));
+
+    let mut out = Vec::new();
+    let mut sorted = sorted.into_iter().peekable();
+
+    while let Some(first) = sorted.next() {
+        let mut set = vec![first];
+        let mut end = first.end();
+
+        while let Some(next) = sorted.next_if(|l| l.start() < end) {
+            end = end.max(next.end());
+            set.push(next);
+        }
+
+        if set.len() > 1 {
+            out.push(place(set, end));
+        }
+    }
+
+    out
+}
+
+/// Greedily assign columns to an overlapping set sorted by start time.
+fn place<T: LessonLike>(set: Vec<&T>, end: DateTime<Utc>) -> Conflict<'_, T> {
+    let start = set[0].start();
+    // end of the last lesson placed in each column
+    let mut column_ends: Vec<DateTime<Utc>> = Vec::new();
+
+    let lessons = set
+        .into_iter()
+        .map(|lesson| {
+            let column = match column_ends.iter().position(|e| *e <= lesson.start()) {
+                Some(column) => {
+                    column_ends[column] = lesson.end();
+                    column
+                }
+                None => {
+                    column_ends.push(lesson.end());
+                    column_ends.len() - 1
+                }
+            };
+
+            Placement { lesson, column }
+        })
+        .collect();
+
+    Conflict {
+        start,
+        end,
+        columns: column_ends.len(),
+        lessons,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use crate::Lesson;
+
+    fn lesson(start: u32, end: u32) -> Lesson {
+        Lesson {
+            teachers: Vec::new(),
+            locations: Vec::new(),
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, start, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, end, 0, 0).unwrap(),
+            course: None,
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn conflicts() {
+        let lessons = [
+            lesson(8, 10),
+            lesson(9, 11),
+            lesson(10, 12),
+            lesson(12, 13),
+            lesson(14, 15),
+            lesson(14, 16),
+        ];
+        let conflicts = super::conflicts(&lessons);
+
+        assert_eq!(conflicts.len(), 2);
+
+        assert_eq!(conflicts[0].lessons.len(), 3);
+        assert_eq!(conflicts[0].columns, 2);
+        assert_eq!(conflicts[0].end, lessons[2].end);
+        let columns = conflicts[0]
+            .lessons
+            .iter()
+            .map(|p| p.column)
+            .collect::<Vec<_>>();
+        assert_eq!(columns, [0, 1, 0]);
+
+        assert_eq!(conflicts[1].lessons.len(), 2);
+        assert_eq!(conflicts[1].columns, 2);
+    }
+}
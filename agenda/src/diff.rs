@@ -0,0 +1,125 @@
+//! Changes between two versions of a schedule.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::LessonLike;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change<'a, T> {
+    /// The lesson is new.
+    Added { lesson: &'a T },
+    /// The lesson is gone, e.g. because it was cancelled.
+    Removed { lesson: &'a T },
+    /// The lesson starts or ends at another time (and might have other
+    /// changes as well).
+    Moved { before: &'a T, after: &'a T },
+    /// Same time, but something else changed, such as the location.
+    Changed { before: &'a T, after: &'a T },
+}
+
+/// Lessons are matched by [`LessonLike::series`], falling back to
+/// [`LessonLike::id`]. Lessons moved to another weekday therefore show up as
+/// removed and added.
+fn key<T: LessonLike>(lesson: &T) -> Uuid {
+    lesson.series().unwrap_or_else(|| lesson.id())
+}
+
+fn same_details<T: LessonLike>(a: &T, b: &T) -> bool {
+    a.course() == b.course()
+        && a.teachers() == b.teachers()
+        && a.locations() == b.locations()
+        && a.group() == b.group()
+}
+
+/// List what changed from `before` to `after`, which would typically be the
+/// same week fetched at different times.
+pub fn diff<'a, T: LessonLike>(before: &'a [T], after: &'a [T]) -> Vec<Change<'a, T>> {
+    let mut unmatched = after.iter().map(|l| (key(l), l)).collect::<HashMap<_, _>>();
+    let mut changes = Vec::new();
+
+    for b in before {
+        match unmatched.remove(&key(b)) {
+            None => changes.push(Change::Removed { lesson: b }),
+            Some(a) if (a.start(), a.end()) != (b.start(), b.end()) => {
+                changes.push(Change::Moved {
+                    before: b,
+                    after: a,
+                })
+            }
+            Some(a) if !same_details(a, b) => changes.push(Change::Changed {
+                before: b,
+                after: a,
+            }),
+            Some(_) => {}
+        }
+    }
+
+    changes.extend(
+        after
+            .iter()
+            .filter(|l| unmatched.contains_key(&key(*l)))
+            .map(|lesson| Change::Added { lesson }),
+    );
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{diff, Change};
+    use crate::Lesson;
+
+    fn lesson(series: u128, hour: u32, location: &str) -> Lesson {
+        Lesson {
+            teachers: Vec::new(),
+            locations: vec![location.to_owned()],
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, hour, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, hour + 1, 0, 0).unwrap(),
+            course: None,
+            id: Uuid::from_u128(series << 8 | u128::from(hour)),
+            series: Some(Uuid::from_u128(series)),
+            color: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn diff_lessons() {
+        let before = [
+            lesson(1, 8, "A1"),
+            lesson(2, 9, "A1"),
+            lesson(3, 10, "A1"),
+            lesson(4, 11, "A1"),
+        ];
+        let after = [
+            lesson(1, 8, "A1"),
+            lesson(2, 13, "A1"),
+            lesson(3, 10, "B2"),
+            lesson(5, 14, "A1"),
+        ];
+
+        assert_eq!(
+            diff(&before, &after),
+            [
+                Change::Moved {
+                    before: &before[1],
+                    after: &after[1]
+                },
+                Change::Changed {
+                    before: &before[2],
+                    after: &after[2]
+                },
+                Change::Removed { lesson: &before[3] },
+                Change::Added { lesson: &after[3] },
+            ]
+        );
+    }
+}
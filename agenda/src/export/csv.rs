@@ -0,0 +1,97 @@
+use std::{borrow::Cow, fmt::Write};
+
+use chrono::SecondsFormat;
+
+use crate::LessonLike;
+
+const HEADER: [&str; 10] = [
+    "id", "series", "start", "end", "course", "teacher", "location", "group", "color", "source",
+];
+
+/// Escape a field according to RFC 4180.
+fn field(s: &str) -> Cow<str> {
+    if s.contains(['"', ',', '\r', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
+
+/// Write the lessons as CSV with a header row, one lesson per line.
+pub fn to_csv<T: LessonLike>(lessons: impl IntoIterator<Item = T>) -> String {
+    let mut out = HEADER.join(",");
+    out.push_str("\r\n");
+
+    for lesson in lessons {
+        let _ = write!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{}\r\n",
+            lesson.id(),
+            lesson.series().map(|s| s.to_string()).unwrap_or_default(),
+            lesson.start().to_rfc3339_opts(SecondsFormat::Secs, true),
+            lesson.end().to_rfc3339_opts(SecondsFormat::Secs, true),
+            field(&lesson.course().unwrap_or_default()),
+            field(&lesson.teacher().unwrap_or_default()),
+            field(&lesson.location().unwrap_or_default()),
+            field(&lesson.group().unwrap_or_default()),
+            lesson
+                .color()
+                .map(|c| c.to_hex_string())
+                .unwrap_or_default(),
+            field(&lesson.source().unwrap_or_default()),
+        );
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{field, to_csv};
+    use crate::Lesson;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(field("Matematik 2c"), "Matematik 2c");
+        assert_eq!(field("Svenska, engelska"), "\"Svenska, engelska\"");
+        assert_eq!(field("\"Idrott\""), "\"\"\"Idrott\"\"\"");
+        assert_eq!(field("rad 1\nrad 2"), "\"rad 1\nrad 2\"");
+        assert_eq!(field("rad 1\r\nrad 2"), "\"rad 1\r\nrad 2\"");
+    }
+
+    #[test]
+    fn rows() {
+        let lesson = Lesson {
+            teachers: vec!["ABC".to_owned(), "DEF".to_owned()],
+            locations: vec!["A1".to_owned()],
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, 8, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, 9, 0, 0).unwrap(),
+            course: Some("Fysik, \"labb\"".to_owned()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+        let csv = to_csv([lesson]);
+        let mut lines = csv.split("\r\n");
+
+        assert_eq!(
+            lines.next(),
+            Some("id,series,start,end,course,teacher,location,group,color,source")
+        );
+        assert_eq!(
+            lines.next(),
+            Some(concat!(
+                "00000000-0000-0000-0000-000000000000,,",
+                "2023-01-09T08:00:00Z,2023-01-09T09:00:00Z,",
+                "\"Fysik, \"\"labb\"\"\",\"ABC, DEF\",A1,,,"
+            ))
+        );
+        assert_eq!(lines.next(), Some(""));
+        assert_eq!(lines.next(), None);
+    }
+}
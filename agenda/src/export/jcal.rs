@@ -0,0 +1,129 @@
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use super::{calendar_properties, day_off_properties, event_properties, Property};
+use crate::{DayOff, LessonLike, Render};
+
+fn property(p: Property) -> Value {
+    json!([p.name, {}, p.kind.as_str(), p.value])
+}
+
+fn event(props: Vec<Property>) -> Value {
+    let props = props.into_iter().map(property).collect::<Vec<_>>();
+    json!(["vevent", props, []])
+}
+
+/// Build a jCal ([RFC 7265](https://www.rfc-editor.org/rfc/rfc7265)) document.
+pub fn to_jcal<T: LessonLike>(
+    lessons: impl IntoIterator<Item = T>,
+    days: &[DayOff],
+    render: &Render,
+) -> Value {
+    let stamp = Utc::now();
+
+    let events = lessons
+        .into_iter()
+        .map(|lesson| event(event_properties(&lesson, render, stamp)))
+        .chain(
+            days.iter()
+                .map(|day| event(day_off_properties(day, render, stamp))),
+        )
+        .collect::<Vec<_>>();
+
+    let props = calendar_properties()
+        .into_iter()
+        .map(property)
+        .collect::<Vec<_>>();
+
+    json!(["vcalendar", props, events])
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use super::to_jcal;
+    use crate::{
+        holiday::{Reason, SchoolBreak},
+        DayOff, Lesson, Render,
+    };
+
+    fn find<'a>(props: &'a Value, name: &str) -> Option<&'a Value> {
+        props
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|prop| prop[0] == name)
+    }
+
+    #[test]
+    fn structure() {
+        let lesson = Lesson {
+            teachers: vec!["ABC".to_owned()],
+            locations: vec!["A1".to_owned()],
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, 8, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, 9, 0, 0).unwrap(),
+            course: Some("Fysik".to_owned()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+        let day = DayOff {
+            first: NaiveDate::from_ymd_opt(2023, 2, 20).unwrap(),
+            last: NaiveDate::from_ymd_opt(2023, 2, 24).unwrap(),
+            reason: Reason::Break(SchoolBreak::Sports),
+        };
+        let jcal = to_jcal([lesson], &[day], &Render::default());
+
+        assert_eq!(jcal[0], "vcalendar");
+        assert_eq!(
+            find(&jcal[1], "version"),
+            Some(&json!(["version", {}, "text", "2.0"]))
+        );
+        assert!(find(&jcal[1], "prodid").is_some());
+
+        let events = jcal[2].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+
+        let lesson = &events[0];
+        assert_eq!(lesson[0], "vevent");
+        assert_eq!(lesson[2], json!([]));
+        assert_eq!(
+            find(&lesson[1], "uid"),
+            Some(&json!([
+                "uid",
+                {},
+                "text",
+                "00000000-0000-0000-0000-000000000000"
+            ]))
+        );
+        assert_eq!(
+            find(&lesson[1], "dtstart"),
+            Some(&json!(["dtstart", {}, "date-time", "2023-01-09T08:00:00Z"]))
+        );
+        assert_eq!(
+            find(&lesson[1], "summary"),
+            Some(&json!(["summary", {}, "text", "Fysik"]))
+        );
+        assert_eq!(
+            find(&lesson[1], "location"),
+            Some(&json!(["location", {}, "text", "A1"]))
+        );
+        assert_eq!(find(&lesson[1], "categories"), None);
+
+        let day = &events[1];
+        assert_eq!(day[0], "vevent");
+        assert_eq!(
+            find(&day[1], "dtstart"),
+            Some(&json!(["dtstart", {}, "date", "2023-02-20"]))
+        );
+        assert_eq!(
+            find(&day[1], "dtend"),
+            Some(&json!(["dtend", {}, "date", "2023-02-25"]))
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Serializers for formats other than iCalendar.
+//!
+//! Every exporter except CSV writes the same properties as
+//! [`LessonLike::to_event`] and [`DayOff::to_event`]. CSV has one column per
+//! field instead, and no days off.
+
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+
+use crate::{DayOff, LessonLike, Render};
+
+mod csv;
+mod jcal;
+mod xcal;
+
+pub use self::csv::to_csv;
+pub use jcal::to_jcal;
+pub use xcal::to_xcal;
+
+/// Product identifier written to jCal and xCal documents.
+pub const PRODID: &str = concat!("-//skool//skool-agenda ", env!("CARGO_PKG_VERSION"), "//SV");
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Text,
+    Date,
+    DateTime,
+}
+
+impl Kind {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Kind::Text => "text",
+            Kind::Date => "date",
+            Kind::DateTime => "date-time",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Property {
+    name: &'static str,
+    kind: Kind,
+    value: String,
+}
+
+impl Property {
+    fn text(name: &'static str, value: impl Into<String>) -> Self {
+        Self {
+            name,
+            kind: Kind::Text,
+            value: value.into(),
+        }
+    }
+
+    fn date(name: &'static str, value: NaiveDate) -> Self {
+        Self {
+            name,
+            kind: Kind::Date,
+            value: value.format("%Y-%m-%d").to_string(),
+        }
+    }
+
+    fn date_time(name: &'static str, value: DateTime<Utc>) -> Self {
+        Self {
+            name,
+            kind: Kind::DateTime,
+            value: value.to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+}
+
+pub(crate) fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn calendar_properties() -> [Property; 2] {
+    [
+        Property::text("version", "2.0"),
+        Property::text("prodid", PRODID),
+    ]
+}
+
+fn event_properties<T: LessonLike>(
+    lesson: &T,
+    render: &Render,
+    stamp: DateTime<Utc>,
+) -> Vec<Property> {
+    let mut props = vec![
+        Property::text("uid", lesson.id().to_string()),
+        Property::date_time("dtstamp", stamp),
+        Property::date_time("dtstart", lesson.start()),
+        Property::date_time("dtend", lesson.end()),
+        Property::text("summary", render.summary(lesson)),
+    ];
+
+    if let Some(location) = lesson.location() {
+        props.push(Property::text("location", location));
+    }
+
+    if let Some(description) = render.description(lesson) {
+        props.push(Property::text("description", description));
+    }
+
+    if let Some(source) = lesson.source() {
+        props.push(Property::text("categories", source));
+    }
+
+    props
+}
+
+fn day_off_properties(day: &DayOff, render: &Render, stamp: DateTime<Utc>) -> Vec<Property> {
+    vec![
+        Property::text("uid", day.uid().to_string()),
+        Property::date_time("dtstamp", stamp),
+        Property::date("dtstart", day.first),
+        Property::date("dtend", day.last.succ_opt().unwrap_or(day.last)),
+        Property::text("summary", day.reason.name(render.locale)),
+    ]
+}
@@ -0,0 +1,90 @@
+use std::fmt::Write;
+
+use chrono::Utc;
+
+use super::{calendar_properties, day_off_properties, escape_xml, event_properties, Property};
+use crate::{DayOff, LessonLike, Render};
+
+const NAMESPACE: &str = "urn:ietf:params:xml:ns:icalendar-2.0";
+
+fn write_properties(out: &mut String, props: impl IntoIterator<Item = Property>) {
+    out.push_str("<properties>");
+    for Property { name, kind, value } in props {
+        let kind = kind.as_str();
+        let _ = write!(
+            out,
+            "<{name}><{kind}>{}</{kind}></{name}>",
+            escape_xml(&value)
+        );
+    }
+    out.push_str("</properties>");
+}
+
+/// Build an xCal ([RFC 6321](https://www.rfc-editor.org/rfc/rfc6321)) document.
+pub fn to_xcal<T: LessonLike>(
+    lessons: impl IntoIterator<Item = T>,
+    days: &[DayOff],
+    render: &Render,
+) -> String {
+    let stamp = Utc::now();
+    let mut out = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?><icalendar xmlns="{NAMESPACE}"><vcalendar>"#
+    );
+
+    write_properties(&mut out, calendar_properties());
+
+    out.push_str("<components>");
+    for lesson in lessons {
+        out.push_str("<vevent>");
+        write_properties(&mut out, event_properties(&lesson, render, stamp));
+        out.push_str("</vevent>");
+    }
+    for day in days {
+        out.push_str("<vevent>");
+        write_properties(&mut out, day_off_properties(day, render, stamp));
+        out.push_str("</vevent>");
+    }
+    out.push_str("</components></vcalendar></icalendar>");
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::to_xcal;
+    use crate::{Lesson, Render};
+
+    #[test]
+    fn structure() {
+        let lesson = Lesson {
+            teachers: vec!["ABC".to_owned()],
+            locations: vec!["A1".to_owned()],
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, 8, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, 9, 0, 0).unwrap(),
+            course: Some("Fysik & <kemi> \"labb\" 'A'".to_owned()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+        let xcal = to_xcal([lesson], &[], &Render::default());
+
+        assert!(xcal.starts_with(concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<icalendar xmlns="urn:ietf:params:xml:ns:icalendar-2.0">"#,
+            "<vcalendar><properties><version><text>2.0</text></version>"
+        )));
+        assert!(xcal.ends_with("</vevent></components></vcalendar></icalendar>"));
+        assert_eq!(xcal.matches("<vevent>").count(), 1);
+        assert!(xcal.contains("<dtstart><date-time>2023-01-09T08:00:00Z</date-time></dtstart>"));
+        assert!(xcal.contains(concat!(
+            "<summary><text>",
+            "Fysik &amp; &lt;kemi&gt; &quot;labb&quot; &apos;A&apos;",
+            "</text></summary>"
+        )));
+    }
+}
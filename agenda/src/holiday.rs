@@ -0,0 +1,360 @@
+//! Days without lessons: Swedish public holidays and school breaks.
+
+use std::collections::BTreeSet;
+
+use chrono::{Datelike, Duration, NaiveDate, TimeZone, Weekday};
+use icalendar::{Component, Event, EventLike};
+use uuid::Uuid;
+
+use crate::{LessonLike, Locale};
+
+const UUID_NAMESPACE: Uuid = Uuid::from_bytes([
+    0x3f, 0x0e, 0x5a, 0x8c, 0x6b, 0x1d, 0x4e, 0x27, 0x9a, 0x52, 0xd4, 0x0b, 0x73, 0xe1, 0x88, 0x16,
+]);
+
+/// Swedish public holidays, plus the eves that are days off in practice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Holiday {
+    NewYearsDay,
+    Epiphany,
+    GoodFriday,
+    EasterEve,
+    EasterDay,
+    EasterMonday,
+    MayDay,
+    Ascension,
+    NationalDay,
+    Whitsun,
+    MidsummerEve,
+    MidsummerDay,
+    AllSaintsDay,
+    ChristmasEve,
+    ChristmasDay,
+    BoxingDay,
+    NewYearsEve,
+}
+
+impl Holiday {
+    /// Whether the day is an official public holiday ("allmän helgdag"),
+    /// rather than an eve.
+    pub const fn is_public(self) -> bool {
+        !matches!(
+            self,
+            Holiday::EasterEve
+                | Holiday::MidsummerEve
+                | Holiday::ChristmasEve
+                | Holiday::NewYearsEve
+        )
+    }
+
+    pub const fn name(self, locale: Locale) -> &'static str {
+        match (locale, self) {
+            (Locale::Sv, Holiday::NewYearsDay) => "Nyårsdagen",
+            (Locale::Sv, Holiday::Epiphany) => "Trettondedag jul",
+            (Locale::Sv, Holiday::GoodFriday) => "Långfredagen",
+            (Locale::Sv, Holiday::EasterEve) => "Påskafton",
+            (Locale::Sv, Holiday::EasterDay) => "Påskdagen",
+            (Locale::Sv, Holiday::EasterMonday) => "Annandag påsk",
+            (Locale::Sv, Holiday::MayDay) => "Första maj",
+            (Locale::Sv, Holiday::Ascension) => "Kristi himmelsfärdsdag",
+            (Locale::Sv, Holiday::NationalDay) => "Sveriges nationaldag",
+            (Locale::Sv, Holiday::Whitsun) => "Pingstdagen",
+            (Locale::Sv, Holiday::MidsummerEve) => "Midsommarafton",
+            (Locale::Sv, Holiday::MidsummerDay) => "Midsommardagen",
+            (Locale::Sv, Holiday::AllSaintsDay) => "Alla helgons dag",
+            (Locale::Sv, Holiday::ChristmasEve) => "Julafton",
+            (Locale::Sv, Holiday::ChristmasDay) => "Juldagen",
+            (Locale::Sv, Holiday::BoxingDay) => "Annandag jul",
+            (Locale::Sv, Holiday::NewYearsEve) => "Nyårsafton",
+            (Locale::En, Holiday::NewYearsDay) => "New Year's Day",
+            (Locale::En, Holiday::Epiphany) => "Epiphany",
+            (Locale::En, Holiday::GoodFriday) => "Good Friday",
+            (Locale::En, Holiday::EasterEve) => "Easter Eve",
+            (Locale::En, Holiday::EasterDay) => "Easter Day",
+            (Locale::En, Holiday::EasterMonday) => "Easter Monday",
+            (Locale::En, Holiday::MayDay) => "May Day",
+            (Locale::En, Holiday::Ascension) => "Ascension Day",
+            (Locale::En, Holiday::NationalDay) => "National Day of Sweden",
+            (Locale::En, Holiday::Whitsun) => "Whitsunday",
+            (Locale::En, Holiday::MidsummerEve) => "Midsummer Eve",
+            (Locale::En, Holiday::MidsummerDay) => "Midsummer Day",
+            (Locale::En, Holiday::AllSaintsDay) => "All Saints' Day",
+            (Locale::En, Holiday::ChristmasEve) => "Christmas Eve",
+            (Locale::En, Holiday::ChristmasDay) => "Christmas Day",
+            (Locale::En, Holiday::BoxingDay) => "Boxing Day",
+            (Locale::En, Holiday::NewYearsEve) => "New Year's Eve",
+        }
+    }
+}
+
+/// A school break, named after the time of year it falls on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SchoolBreak {
+    Autumn,
+    Christmas,
+    Sports,
+    Easter,
+    Summer,
+    Other,
+}
+
+impl SchoolBreak {
+    /// Guess which break spans `first..=last`.
+    fn of(first: NaiveDate, last: NaiveDate) -> Self {
+        let overlaps = |a: NaiveDate, b: NaiveDate| first <= b && a <= last;
+        let easter = easter(first.year());
+
+        if (6..=8).contains(&first.month()) && (6..=8).contains(&last.month()) {
+            SchoolBreak::Summer
+        } else if first.month() == 12 || last.month() == 1 {
+            SchoolBreak::Christmas
+        } else if overlaps(easter - Duration::weeks(1), easter + Duration::weeks(1)) {
+            SchoolBreak::Easter
+        } else if (43..=45).contains(&first.iso_week().week()) {
+            SchoolBreak::Autumn
+        } else if (7..=10).contains(&first.iso_week().week()) {
+            SchoolBreak::Sports
+        } else {
+            SchoolBreak::Other
+        }
+    }
+
+    pub const fn name(self, locale: Locale) -> &'static str {
+        match (locale, self) {
+            (Locale::Sv, SchoolBreak::Autumn) => "Höstlov",
+            (Locale::Sv, SchoolBreak::Christmas) => "Jullov",
+            (Locale::Sv, SchoolBreak::Sports) => "Sportlov",
+            (Locale::Sv, SchoolBreak::Easter) => "Påsklov",
+            (Locale::Sv, SchoolBreak::Summer) => "Sommarlov",
+            (Locale::Sv, SchoolBreak::Other) => "Lov",
+            (Locale::En, SchoolBreak::Autumn) => "Autumn break",
+            (Locale::En, SchoolBreak::Christmas) => "Christmas break",
+            (Locale::En, SchoolBreak::Sports) => "Sports break",
+            (Locale::En, SchoolBreak::Easter) => "Easter break",
+            (Locale::En, SchoolBreak::Summer) => "Summer break",
+            (Locale::En, SchoolBreak::Other) => "School break",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Reason {
+    Holiday(Holiday),
+    Break(SchoolBreak),
+}
+
+impl Reason {
+    pub const fn name(self, locale: Locale) -> &'static str {
+        match self {
+            Reason::Holiday(holiday) => holiday.name(locale),
+            Reason::Break(school_break) => school_break.name(locale),
+        }
+    }
+}
+
+/// One or more whole days without lessons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DayOff {
+    pub first: NaiveDate,
+    /// Inclusive.
+    pub last: NaiveDate,
+    pub reason: Reason,
+}
+
+impl DayOff {
+    fn holiday(date: NaiveDate, holiday: Holiday) -> Self {
+        Self {
+            first: date,
+            last: date,
+            reason: Reason::Holiday(holiday),
+        }
+    }
+
+    /// Derived from the dates and reason, so it's the same in every export.
+    pub fn uid(&self) -> Uuid {
+        let characteristic = format!(
+            "{}/{}/{}",
+            self.first,
+            self.last,
+            self.reason.name(Locale::En)
+        );
+        Uuid::new_v5(&UUID_NAMESPACE, characteristic.as_bytes())
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.first <= date && date <= self.last
+    }
+
+    /// An all-day event spanning the days.
+    pub fn to_event(&self, locale: Locale) -> Event {
+        let mut event = Event::new();
+
+        // DTEND is exclusive
+        event
+            .starts(self.first)
+            .ends(self.last.succ_opt().unwrap_or(self.last))
+            .uid(&self.uid().to_string())
+            .summary(self.reason.name(locale));
+
+        event.done()
+    }
+}
+
+/// Date of Easter Day in the Gregorian calendar, using the anonymous
+/// Gregorian algorithm ("Meeus/Jones/Butcher").
+pub fn easter(year: i32) -> NaiveDate {
+    let a = year.rem_euclid(19);
+    let b = year.div_euclid(100);
+    let c = year.rem_euclid(100);
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+/// The first `weekday` on or after `date`.
+fn next_weekday(date: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let days = (7 + weekday.num_days_from_monday() - date.weekday().num_days_from_monday()) % 7;
+    date + Duration::days(days.into())
+}
+
+/// All holidays in `year`, in chronological order.
+pub fn holidays(year: i32) -> Vec<DayOff> {
+    let date = |month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    let easter = easter(year);
+    let midsummer = next_weekday(date(6, 20), Weekday::Sat);
+
+    let mut days = vec![
+        DayOff::holiday(date(1, 1), Holiday::NewYearsDay),
+        DayOff::holiday(date(1, 6), Holiday::Epiphany),
+        DayOff::holiday(easter - Duration::days(2), Holiday::GoodFriday),
+        DayOff::holiday(easter - Duration::days(1), Holiday::EasterEve),
+        DayOff::holiday(easter, Holiday::EasterDay),
+        DayOff::holiday(easter + Duration::days(1), Holiday::EasterMonday),
+        DayOff::holiday(date(5, 1), Holiday::MayDay),
+        DayOff::holiday(easter + Duration::days(39), Holiday::Ascension),
+        DayOff::holiday(date(6, 6), Holiday::NationalDay),
+        DayOff::holiday(easter + Duration::days(49), Holiday::Whitsun),
+        DayOff::holiday(midsummer - Duration::days(1), Holiday::MidsummerEve),
+        DayOff::holiday(midsummer, Holiday::MidsummerDay),
+        DayOff::holiday(
+            next_weekday(date(10, 31), Weekday::Sat),
+            Holiday::AllSaintsDay,
+        ),
+        DayOff::holiday(date(12, 24), Holiday::ChristmasEve),
+        DayOff::holiday(date(12, 25), Holiday::ChristmasDay),
+        DayOff::holiday(date(12, 26), Holiday::BoxingDay),
+        DayOff::holiday(date(12, 31), Holiday::NewYearsEve),
+    ];
+
+    days.sort();
+    days
+}
+
+/// Holidays from `first` through `last`.
+pub fn holidays_between(first: NaiveDate, last: NaiveDate) -> Vec<DayOff> {
+    (first.year()..=last.year())
+        .flat_map(holidays)
+        .filter(|day| first <= day.first && day.last <= last)
+        .collect()
+}
+
+/// Guess school breaks from whole weeks without lessons. Only weeks between
+/// the first and the last week with lessons are considered, since the
+/// schedule might simply not be published yet. Days are split in `tz`.
+pub fn school_breaks<T: LessonLike, Tz: TimeZone>(lessons: &[T], tz: &Tz) -> Vec<DayOff> {
+    let mondays = lessons
+        .iter()
+        .map(|l| {
+            let date = l.start().with_timezone(tz).date_naive();
+            date - Duration::days(date.weekday().num_days_from_monday().into())
+        })
+        .collect::<BTreeSet<_>>();
+
+    let mut breaks = Vec::new();
+
+    for (a, b) in mondays.iter().zip(mondays.iter().skip(1)) {
+        if *b - *a > Duration::weeks(1) {
+            let first = *a + Duration::weeks(1);
+            let last = *b - Duration::days(1);
+            breaks.push(DayOff {
+                first,
+                last,
+                reason: Reason::Break(SchoolBreak::of(first, last)),
+            });
+        }
+    }
+
+    breaks
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{easter, holidays, school_breaks, Holiday, Reason, SchoolBreak};
+    use crate::Lesson;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn holidays_2023() {
+        assert_eq!(easter(2023), date(2023, 4, 9));
+        assert_eq!(easter(2024), date(2024, 3, 31));
+        assert_eq!(easter(2025), date(2025, 4, 20));
+
+        let find = |holiday| {
+            holidays(2023)
+                .into_iter()
+                .find(|d| d.reason == Reason::Holiday(holiday))
+                .unwrap()
+                .first
+        };
+
+        assert_eq!(find(Holiday::Ascension), date(2023, 5, 18));
+        assert_eq!(find(Holiday::Whitsun), date(2023, 5, 28));
+        assert_eq!(find(Holiday::MidsummerEve), date(2023, 6, 23));
+        assert_eq!(find(Holiday::MidsummerDay), date(2023, 6, 24));
+        assert_eq!(find(Holiday::AllSaintsDay), date(2023, 11, 4));
+    }
+
+    #[test]
+    fn breaks() {
+        let lesson = |d: NaiveDate| Lesson {
+            teachers: Vec::new(),
+            locations: Vec::new(),
+            group: None,
+            start: Utc.from_utc_datetime(&d.and_hms_opt(8, 0, 0).unwrap()),
+            end: Utc.from_utc_datetime(&d.and_hms_opt(9, 0, 0).unwrap()),
+            course: None,
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+
+        let lessons = [
+            lesson(date(2023, 10, 20)),
+            lesson(date(2023, 11, 6)),
+            lesson(date(2023, 11, 13)),
+        ];
+        let breaks = school_breaks(&lessons, &Utc);
+
+        assert_eq!(breaks.len(), 1);
+        assert_eq!(breaks[0].first, date(2023, 10, 23));
+        assert_eq!(breaks[0].last, date(2023, 11, 5));
+        assert_eq!(breaks[0].reason, Reason::Break(SchoolBreak::Autumn));
+    }
+}
@@ -1,17 +1,60 @@
 #![doc = include_str!("../README.md")]
 use std::borrow::Cow;
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 
 use csscolorparser::Color;
-use icalendar::{Calendar, Component, Event, EventLike};
+use icalendar::{Alarm, Calendar, Component, Event, EventLike};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+pub mod alarm;
+pub mod conflict;
+pub mod diff;
+pub mod export;
+pub mod holiday;
+pub mod render;
+pub mod schedule;
+pub mod source;
+pub mod svg;
+
+pub use alarm::AlarmPolicy;
+pub use conflict::{conflicts, Conflict};
+pub use diff::{diff, Change};
+pub use holiday::DayOff;
+pub use render::{Locale, Render, Template};
+pub use schedule::Schedule;
+pub use source::{merge, Source};
+
+/// Join a list for display, e.g. `["A", "B"]` becomes `"A, B"`.
+fn join<'a>(mut items: Vec<Cow<'a, str>>) -> Option<Cow<'a, str>> {
+    match items.len() {
+        0 => None,
+        1 => items.pop(),
+        _ => Some(items.join(", ").into()),
+    }
+}
+
 pub trait LessonLike {
-    fn teacher(&self) -> Option<Cow<str>>;
+    fn teachers(&self) -> Vec<Cow<str>>;
+
+    /// All teachers, comma separated.
+    fn teacher(&self) -> Option<Cow<str>> {
+        join(self.teachers())
+    }
+
+    fn locations(&self) -> Vec<Cow<str>>;
+
+    /// All locations, comma separated.
+    fn location(&self) -> Option<Cow<str>> {
+        join(self.locations())
+    }
 
-    fn location(&self) -> Option<Cow<str>>;
+    /// Name of the group or block the lesson is taught to, if it isn't the
+    /// whole class.
+    fn group(&self) -> Option<Cow<str>> {
+        None
+    }
 
     fn start(&self) -> DateTime<Utc>;
 
@@ -21,51 +64,228 @@ pub trait LessonLike {
         self.end() - self.start()
     }
 
+    fn overlaps(&self, other: &impl LessonLike) -> bool {
+        self.start() < other.end() && other.start() < self.end()
+    }
+
     fn course(&self) -> Option<Cow<str>>;
 
     fn id(&self) -> Uuid;
 
+    /// Identifies a recurring lesson, e.g. "Math on Mondays". Unlike
+    /// [`LessonLike::id`], it stays the same from week to week and when the
+    /// lesson is moved.
+    fn series(&self) -> Option<Uuid> {
+        None
+    }
+
     fn color(&self) -> Option<&Color>;
 
-    fn to_event(&self) -> Event {
+    /// Name of the [`Source`] the lesson was merged from, if any.
+    fn source(&self) -> Option<Cow<str>> {
+        None
+    }
+
+    fn to_event(&self, render: &Render, alarm: Option<Alarm>) -> Event
+    where
+        Self: Sized,
+    {
         let mut event = Event::new();
 
         event
             .starts(self.start())
             .ends(self.end())
             .uid(&self.id().to_string())
-            .summary(&self.course().unwrap_or_else(|| "(Namnlös)".into()));
+            .summary(&render.summary(self));
 
         if let Some(location) = self.location() {
             event.location(&location);
         }
 
-        if let Some(teacher) = self.teacher() {
-            event.description(&teacher);
+        if let Some(description) = render.description(self) {
+            event.description(&description);
+        }
+
+        if let Some(source) = self.source() {
+            event.add_property("CATEGORIES", &source);
+        }
+
+        if let Some(alarm) = alarm {
+            event.alarm(alarm);
         }
 
         event.done()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl<T: LessonLike + ?Sized> LessonLike for &T {
+    fn teachers(&self) -> Vec<Cow<str>> {
+        (**self).teachers()
+    }
+
+    fn teacher(&self) -> Option<Cow<str>> {
+        (**self).teacher()
+    }
+
+    fn locations(&self) -> Vec<Cow<str>> {
+        (**self).locations()
+    }
+
+    fn location(&self) -> Option<Cow<str>> {
+        (**self).location()
+    }
+
+    fn group(&self) -> Option<Cow<str>> {
+        (**self).group()
+    }
+
+    fn start(&self) -> DateTime<Utc> {
+        (**self).start()
+    }
+
+    fn end(&self) -> DateTime<Utc> {
+        (**self).end()
+    }
+
+    fn course(&self) -> Option<Cow<str>> {
+        (**self).course()
+    }
+
+    fn id(&self) -> Uuid {
+        (**self).id()
+    }
+
+    fn series(&self) -> Option<Uuid> {
+        (**self).series()
+    }
+
+    fn color(&self) -> Option<&Color> {
+        (**self).color()
+    }
+
+    fn source(&self) -> Option<Cow<str>> {
+        (**self).source()
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(from = "LessonDe")]
 pub struct Lesson {
-    pub teacher: Option<String>,
-    pub location: Option<String>,
+    pub teachers: Vec<String>,
+    pub locations: Vec<String>,
+    pub group: Option<String>,
     pub start: DateTime<Utc>,
     pub end: DateTime<Utc>,
     pub course: Option<String>,
     pub id: Uuid,
+    pub series: Option<Uuid>,
     pub color: Option<Color>,
+    /// Name of the [`Source`] the lesson was merged from, for lessons that
+    /// have been taken out of their [`source::Tagged`].
+    pub source: Option<String>,
+}
+
+/// Lessons used to have a single `teacher` and `location`, which are still
+/// accepted.
+#[derive(Deserialize)]
+struct LessonDe {
+    teachers: Option<Vec<String>>,
+    teacher: Option<String>,
+    locations: Option<Vec<String>>,
+    location: Option<String>,
+    #[serde(default)]
+    group: Option<String>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    course: Option<String>,
+    id: Uuid,
+    #[serde(default)]
+    series: Option<Uuid>,
+    color: Option<Color>,
+    #[serde(default)]
+    source: Option<String>,
+}
+
+impl From<LessonDe> for Lesson {
+    fn from(de: LessonDe) -> Self {
+        Self {
+            teachers: de
+                .teachers
+                .unwrap_or_else(|| de.teacher.into_iter().collect()),
+            locations: de
+                .locations
+                .unwrap_or_else(|| de.location.into_iter().collect()),
+            group: de.group,
+            start: de.start,
+            end: de.end,
+            course: de.course,
+            id: de.id,
+            series: de.series,
+            color: de.color,
+            source: de.source,
+        }
+    }
+}
+
+/// How any [`LessonLike`] is serialized. `teacher` and `location` are the
+/// lists joined together, for clients that predate `teachers` and
+/// `locations`.
+#[derive(Serialize)]
+pub(crate) struct LessonSer<'a> {
+    teachers: Vec<Cow<'a, str>>,
+    teacher: Option<Cow<'a, str>>,
+    locations: Vec<Cow<'a, str>>,
+    location: Option<Cow<'a, str>>,
+    group: Option<Cow<'a, str>>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    course: Option<Cow<'a, str>>,
+    id: Uuid,
+    series: Option<Uuid>,
+    color: Option<&'a Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<Cow<'a, str>>,
+}
+
+impl<'a> LessonSer<'a> {
+    pub(crate) fn new<T: LessonLike>(lesson: &'a T) -> Self {
+        Self {
+            teachers: lesson.teachers(),
+            teacher: lesson.teacher(),
+            locations: lesson.locations(),
+            location: lesson.location(),
+            group: lesson.group(),
+            start: lesson.start(),
+            end: lesson.end(),
+            course: lesson.course(),
+            id: lesson.id(),
+            series: lesson.series(),
+            color: lesson.color(),
+            source: lesson.source(),
+        }
+    }
+}
+
+impl Serialize for Lesson {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        LessonSer::new(self).serialize(serializer)
+    }
 }
 
 impl LessonLike for Lesson {
-    fn teacher(&self) -> Option<Cow<str>> {
-        self.teacher.as_ref().map(|s| s.into())
+    fn teachers(&self) -> Vec<Cow<str>> {
+        self.teachers.iter().map(|s| s.into()).collect()
     }
 
-    fn location(&self) -> Option<Cow<str>> {
-        self.location.as_ref().map(|s| s.into())
+    fn locations(&self) -> Vec<Cow<str>> {
+        self.locations.iter().map(|s| s.into()).collect()
+    }
+
+    fn group(&self) -> Option<Cow<str>> {
+        self.group.as_ref().map(|s| s.into())
     }
 
     fn start(&self) -> DateTime<Utc> {
@@ -84,11 +304,33 @@ impl LessonLike for Lesson {
         self.id
     }
 
+    fn series(&self) -> Option<Uuid> {
+        self.series
+    }
+
     fn color(&self) -> Option<&Color> {
         self.color.as_ref()
     }
+
+    fn source(&self) -> Option<Cow<str>> {
+        self.source.as_ref().map(|s| s.into())
+    }
 }
 
-pub fn build_calendar<T: LessonLike>(lessons: impl Iterator<Item = T>) -> Calendar {
-    Calendar::from_iter(lessons.map(|l| l.to_event()))
+/// Build a calendar with reminders according to `alarms`. See
+/// [`AlarmPolicy::plan`] for the meaning of `tz`.
+pub fn build_calendar<T: LessonLike, Tz: TimeZone>(
+    lessons: impl Iterator<Item = T>,
+    render: &Render,
+    alarms: AlarmPolicy,
+    tz: &Tz,
+) -> Calendar {
+    let lessons = lessons.collect::<Vec<_>>();
+    let alarms = alarms.plan(&lessons, tz);
+
+    Calendar::from_iter(
+        lessons
+            .iter()
+            .map(|l| l.to_event(render, alarms.get(l, render))),
+    )
 }
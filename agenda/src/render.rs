@@ -0,0 +1,387 @@
+//! Turning lessons into human readable text.
+
+use std::{borrow::Cow, fmt};
+
+use chrono::Weekday;
+use serde::{Deserialize, Serialize};
+
+use crate::LessonLike;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    Sv,
+    En,
+}
+
+impl Locale {
+    /// The language code, as in serialization.
+    pub const fn code(self) -> &'static str {
+        match self {
+            Locale::Sv => "sv",
+            Locale::En => "en",
+        }
+    }
+
+    /// Summary of lessons without any text.
+    pub const fn untitled(self) -> &'static str {
+        match self {
+            Locale::Sv => "(Namnlös)",
+            Locale::En => "(Untitled)",
+        }
+    }
+
+    /// Summary of lessons that only show that one is busy.
+    pub const fn busy(self) -> &'static str {
+        match self {
+            Locale::Sv => "Upptagen",
+            Locale::En => "Busy",
+        }
+    }
+
+    pub const fn weekday(self, weekday: Weekday) -> &'static str {
+        match (self, weekday) {
+            (Locale::Sv, Weekday::Mon) => "Mån",
+            (Locale::Sv, Weekday::Tue) => "Tis",
+            (Locale::Sv, Weekday::Wed) => "Ons",
+            (Locale::Sv, Weekday::Thu) => "Tor",
+            (Locale::Sv, Weekday::Fri) => "Fre",
+            (Locale::Sv, Weekday::Sat) => "Lör",
+            (Locale::Sv, Weekday::Sun) => "Sön",
+            (Locale::En, Weekday::Mon) => "Mon",
+            (Locale::En, Weekday::Tue) => "Tue",
+            (Locale::En, Weekday::Wed) => "Wed",
+            (Locale::En, Weekday::Thu) => "Thu",
+            (Locale::En, Weekday::Fri) => "Fri",
+            (Locale::En, Weekday::Sat) => "Sat",
+            (Locale::En, Weekday::Sun) => "Sun",
+        }
+    }
+}
+
+impl std::str::FromStr for Locale {
+    type Err = UnknownLocale;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sv" => Ok(Locale::Sv),
+            "en" => Ok(Locale::En),
+            _ => Err(UnknownLocale),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown locale")]
+pub struct UnknownLocale;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Course,
+    Teacher,
+    Location,
+    Group,
+    Source,
+}
+
+impl Placeholder {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "course" => Some(Self::Course),
+            "teacher" => Some(Self::Teacher),
+            "location" | "room" => Some(Self::Location),
+            "group" => Some(Self::Group),
+            "source" => Some(Self::Source),
+            _ => None,
+        }
+    }
+
+    fn value<T: LessonLike>(self, lesson: &T) -> Option<Cow<str>> {
+        match self {
+            Self::Course => lesson.course(),
+            Self::Teacher => lesson.teacher(),
+            Self::Location => lesson.location(),
+            Self::Group => lesson.group(),
+            Self::Source => lesson.source(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("unknown placeholder `{{{0}}}`")]
+    UnknownPlaceholder(String),
+
+    #[error("unclosed `{{`")]
+    Unclosed,
+}
+
+/// A text template such as `"{course} – {room}"`.
+///
+/// Available placeholders are `{course}`, `{teacher}`, `{location}` (or
+/// `{room}`), `{group}` and `{source}`. Multiple teachers or locations are
+/// comma separated. Write `{{` and `}}` for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Part>);
+
+impl Template {
+    /// A template without placeholders.
+    pub fn text(s: impl Into<String>) -> Self {
+        Self(vec![Part::Text(s.into())])
+    }
+
+    pub fn parse(s: &str) -> Result<Self, TemplateError> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::Unclosed),
+                        }
+                    }
+
+                    let placeholder = Placeholder::parse(name.trim())
+                        .ok_or(TemplateError::UnknownPlaceholder(name))?;
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                }
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Self(parts))
+    }
+
+    /// Fill in the placeholders. Text next to a missing value is left out,
+    /// except that one separator is kept between the values on either side of
+    /// it, so `{a} · {b} · {c}` reads `A · C` without `b`. `None` is returned
+    /// if nothing remains.
+    pub fn render<T: LessonLike>(&self, lesson: &T) -> Option<String> {
+        // `None` for text, otherwise the value of the placeholder, if any
+        let values = self
+            .0
+            .iter()
+            .map(|part| match part {
+                Part::Text(_) => None,
+                Part::Placeholder(p) => Some(p.value(lesson).filter(|v| !v.is_empty())),
+            })
+            .collect::<Vec<_>>();
+        let filled = |i: usize| matches!(values.get(i), Some(Some(Some(_))));
+        let missing = |i: usize| matches!(values.get(i), Some(Some(None)));
+
+        let mut out = String::new();
+
+        for (i, part) in self.0.iter().enumerate() {
+            match part {
+                Part::Text(text) => {
+                    let prev = i.checked_sub(1);
+                    let bridges = prev.is_some_and(filled)
+                        && missing(i + 1)
+                        && (i + 1..values.len()).any(filled);
+
+                    if bridges || !(prev.is_some_and(missing) || missing(i + 1)) {
+                        out.push_str(text);
+                    }
+                }
+                Part::Placeholder(_) => {
+                    if let Some(Some(value)) = &values[i] {
+                        out.push_str(value);
+                    }
+                }
+            }
+        }
+
+        if out.trim().is_empty() {
+            None
+        } else {
+            Some(out)
+        }
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.0 {
+            match part {
+                Part::Text(text) => f.write_str(&text.replace('{', "{{").replace('}', "}}"))?,
+                Part::Placeholder(p) => f.write_str(match p {
+                    Placeholder::Course => "{course}",
+                    Placeholder::Teacher => "{teacher}",
+                    Placeholder::Location => "{location}",
+                    Placeholder::Group => "{group}",
+                    Placeholder::Source => "{source}",
+                })?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// How lessons read in calendar exports and images.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Render {
+    pub locale: Locale,
+    pub summary: Template,
+    /// `None` leaves the description out.
+    pub description: Option<Template>,
+}
+
+impl Default for Render {
+    fn default() -> Self {
+        Self {
+            locale: Locale::default(),
+            summary: Template(vec![Part::Placeholder(Placeholder::Course)]),
+            description: Some(Template(vec![Part::Placeholder(Placeholder::Teacher)])),
+        }
+    }
+}
+
+impl Render {
+    pub fn summary<T: LessonLike>(&self, lesson: &T) -> String {
+        self.summary
+            .render(lesson)
+            .unwrap_or_else(|| self.locale.untitled().to_owned())
+    }
+
+    pub fn description<T: LessonLike>(&self, lesson: &T) -> Option<String> {
+        self.description.as_ref()?.render(lesson)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{Render, Template, TemplateError};
+    use crate::Lesson;
+
+    #[test]
+    fn template() {
+        let lesson = Lesson {
+            teachers: vec!["ABC".into(), "DEF".into()],
+            locations: Vec::new(),
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, 8, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, 9, 0, 0).unwrap(),
+            course: Some("Matematik".into()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+
+        let t = Template::parse("{course} – {room}").unwrap();
+        assert_eq!(t.render(&lesson).as_deref(), Some("Matematik"));
+        assert_eq!(t.to_string(), "{course} – {location}");
+
+        let t = Template::parse("{room} – {course}").unwrap();
+        assert_eq!(t.render(&lesson).as_deref(), Some("Matematik"));
+
+        let t = Template::parse("{{{teacher}}}").unwrap();
+        assert_eq!(t.render(&lesson).as_deref(), Some("{ABC, DEF}"));
+
+        assert_eq!(
+            Template::parse("{nope}"),
+            Err(TemplateError::UnknownPlaceholder("nope".into()))
+        );
+        assert_eq!(Template::parse("{course"), Err(TemplateError::Unclosed));
+
+        let render = Render {
+            summary: Template::parse("{location}").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(render.summary(&lesson), "(Namnlös)");
+    }
+
+    #[test]
+    fn missing_values() {
+        let lesson = Lesson {
+            teachers: Vec::new(),
+            locations: vec!["-".into()],
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, 8, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, 9, 0, 0).unwrap(),
+            course: Some("Matte 2c.".into()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+        let render = |s: &str| Template::parse(s).unwrap().render(&lesson);
+
+        assert_eq!(render("{course}").as_deref(), Some("Matte 2c."));
+        assert_eq!(render("{room}").as_deref(), Some("-"));
+        assert_eq!(
+            render("{course} · {teacher} · {room}").as_deref(),
+            Some("Matte 2c. · -")
+        );
+        assert_eq!(
+            render("{teacher} · {group} · {course}").as_deref(),
+            Some("Matte 2c.")
+        );
+        assert_eq!(
+            render("{course} ({teacher}) {group}").as_deref(),
+            Some("Matte 2c.")
+        );
+        assert_eq!(render("Lärare: {teacher}"), None);
+        assert_eq!(render("Lektion").as_deref(), Some("Lektion"));
+    }
+}
@@ -0,0 +1,405 @@
+use std::{borrow::Cow, collections::BTreeMap, ops::Deref};
+
+use chrono::{DateTime, Datelike, IsoWeek, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{conflict, Conflict, Lesson, LessonLike};
+
+/// A collection of lessons, sorted by start time.
+#[derive(Debug, Clone, Serialize)]
+#[serde(transparent)]
+pub struct Schedule<T = Lesson> {
+    lessons: Vec<T>,
+}
+
+/// Sorted like [`Schedule::new`], whatever the order of the input.
+impl<'de, T: LessonLike + Deserialize<'de>> Deserialize<'de> for Schedule<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl<T: LessonLike> Schedule<T> {
+    pub fn new(mut lessons: Vec<T>) -> Self {
+        lessons.sort_by_key(|l| (l.start(), l.end()));
+        Self { lessons }
+    }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.lessons
+    }
+
+    /// Group the lessons by the date they start on in `tz`.
+    pub fn by_day<Tz: TimeZone>(&self, tz: &Tz) -> BTreeMap<NaiveDate, Vec<&T>> {
+        self.group_by(|l| l.start().with_timezone(tz).date_naive())
+    }
+
+    pub fn by_course(&self) -> BTreeMap<Option<String>, Vec<&T>> {
+        self.group_by(|l| l.course().map(|s| s.into_owned()))
+    }
+
+    /// Lessons with several teachers are listed under each of them.
+    pub fn by_teacher(&self) -> BTreeMap<Option<String>, Vec<&T>> {
+        self.group_by_each(|l| l.teachers())
+    }
+
+    /// Lessons in several locations are listed under each of them.
+    pub fn by_location(&self) -> BTreeMap<Option<String>, Vec<&T>> {
+        self.group_by_each(|l| l.locations())
+    }
+
+    pub fn by_group(&self) -> BTreeMap<Option<String>, Vec<&T>> {
+        self.group_by(|l| l.group().map(|s| s.into_owned()))
+    }
+
+    fn group_by<K: Ord>(&self, mut key: impl FnMut(&T) -> K) -> BTreeMap<K, Vec<&T>> {
+        let mut groups = BTreeMap::<_, Vec<_>>::new();
+        for lesson in &self.lessons {
+            groups.entry(key(lesson)).or_default().push(lesson);
+        }
+        groups
+    }
+
+    /// Like [`Schedule::group_by`], but with any number of keys per lesson.
+    /// Lessons without any key end up under `None`.
+    fn group_by_each(
+        &self,
+        mut keys: impl FnMut(&T) -> Vec<Cow<str>>,
+    ) -> BTreeMap<Option<String>, Vec<&T>> {
+        let mut groups = BTreeMap::<_, Vec<_>>::new();
+        for lesson in &self.lessons {
+            let keys = keys(lesson);
+            if keys.is_empty() {
+                groups.entry(None).or_default().push(lesson);
+            }
+            for key in keys {
+                groups
+                    .entry(Some(key.into_owned()))
+                    .or_default()
+                    .push(lesson);
+            }
+        }
+        groups
+    }
+
+    /// Sets of overlapping lessons, see [`conflict::conflicts`].
+    pub fn conflicts(&self) -> Vec<Conflict<'_, T>> {
+        conflict::conflicts(&self.lessons)
+    }
+
+    /// Teaching minutes per course, summed per ISO week and per term.
+    pub fn course_totals<Tz: TimeZone>(&self, tz: &Tz) -> Vec<CourseTotals> {
+        self.by_course()
+            .into_iter()
+            .map(|(course, lessons)| {
+                // keyed by year and week, since `IsoWeek`'s `Ord` is broken
+                // in some versions of chrono
+                let mut weeks = BTreeMap::<(i32, u32), i64>::new();
+                let mut terms = BTreeMap::<Term, i64>::new();
+
+                for lesson in lessons {
+                    let date = lesson.start().with_timezone(tz).date_naive();
+                    let minutes = lesson.duration().num_minutes();
+                    let week = date.iso_week();
+                    *weeks.entry((week.year(), week.week())).or_default() += minutes;
+                    *terms.entry(Term::of(date)).or_default() += minutes;
+                }
+
+                CourseTotals {
+                    course,
+                    weeks: weeks
+                        .into_iter()
+                        .map(|((year, week), minutes)| WeekTotal {
+                            year,
+                            week,
+                            minutes,
+                        })
+                        .collect(),
+                    terms: terms
+                        .into_iter()
+                        .map(|(term, minutes)| TermTotal { term, minutes })
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// The first start, last end and breaks of every day with lessons.
+    pub fn days<Tz: TimeZone>(&self, tz: &Tz) -> Vec<Day> {
+        self.by_day(tz)
+            .into_iter()
+            .filter_map(|(date, lessons)| {
+                let mut lessons = lessons.into_iter();
+                let first = lessons.next()?;
+                let start = first.start();
+                let mut end = first.end();
+                let mut breaks = Vec::new();
+
+                for lesson in lessons {
+                    if lesson.start() > end {
+                        breaks.push(Break {
+                            start: end,
+                            end: lesson.start(),
+                        });
+                    }
+                    end = end.max(lesson.end());
+                }
+
+                Some(Day {
+                    date,
+                    start,
+                    end,
+                    breaks,
+                })
+            })
+            .collect()
+    }
+}
+
+impl<T: LessonLike> From<Vec<T>> for Schedule<T> {
+    fn from(lessons: Vec<T>) -> Self {
+        Self::new(lessons)
+    }
+}
+
+impl<T: LessonLike> FromIterator<T> for Schedule<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl<T> Deref for Schedule<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        &self.lessons
+    }
+}
+
+impl<T> IntoIterator for Schedule<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.lessons.into_iter()
+    }
+}
+
+/// Half of a Swedish school year.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Season {
+    /// Vårtermin, January through July.
+    Spring,
+    /// Hösttermin, August through December.
+    Autumn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Term {
+    pub year: i32,
+    pub season: Season,
+}
+
+impl Term {
+    pub fn of(date: NaiveDate) -> Self {
+        let season = if date.month() <= 7 {
+            Season::Spring
+        } else {
+            Season::Autumn
+        };
+
+        Self {
+            year: date.year(),
+            season,
+        }
+    }
+
+    pub fn first_day(&self) -> NaiveDate {
+        let month = match self.season {
+            Season::Spring => 1,
+            Season::Autumn => 8,
+        };
+        NaiveDate::from_ymd_opt(self.year, month, 1).unwrap()
+    }
+
+    pub fn last_day(&self) -> NaiveDate {
+        let (month, day) = match self.season {
+            Season::Spring => (7, 31),
+            Season::Autumn => (12, 31),
+        };
+        NaiveDate::from_ymd_opt(self.year, month, day).unwrap()
+    }
+
+    /// All ISO weeks that overlap the term.
+    pub fn weeks(&self) -> impl Iterator<Item = IsoWeek> {
+        // compared by year and week, since `IsoWeek`'s `Ord` is broken in
+        // some versions of chrono
+        let last = self.last_day().iso_week();
+        let last = (last.year(), last.week());
+        self.first_day()
+            .iter_weeks()
+            .map(|d| d.iso_week())
+            .take_while(move |w| (w.year(), w.week()) <= last)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CourseTotals {
+    pub course: Option<String>,
+    pub weeks: Vec<WeekTotal>,
+    pub terms: Vec<TermTotal>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WeekTotal {
+    pub year: i32,
+    pub week: u32,
+    pub minutes: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TermTotal {
+    #[serde(flatten)]
+    pub term: Term,
+    pub minutes: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Day {
+    pub date: NaiveDate,
+    /// Start of the first lesson.
+    pub start: DateTime<Utc>,
+    /// End of the last lesson.
+    pub end: DateTime<Utc>,
+    pub breaks: Vec<Break>,
+}
+
+/// Time between two lessons on the same day.
+#[derive(Debug, Clone, Serialize)]
+pub struct Break {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{Schedule, Season, Term};
+    use crate::Lesson;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn weeks(term: Term) -> Vec<(i32, u32)> {
+        term.weeks().map(|w| (w.year(), w.week())).collect()
+    }
+
+    #[test]
+    fn term_of() {
+        let term = |year, season| Term { year, season };
+
+        assert_eq!(Term::of(date(2023, 1, 1)), term(2023, Season::Spring));
+        assert_eq!(Term::of(date(2023, 7, 31)), term(2023, Season::Spring));
+        assert_eq!(Term::of(date(2023, 8, 1)), term(2023, Season::Autumn));
+        assert_eq!(Term::of(date(2023, 12, 31)), term(2023, Season::Autumn));
+        // belongs to ISO week 2020-W53, but to the calendar year 2021
+        assert_eq!(Term::of(date(2021, 1, 1)), term(2021, Season::Spring));
+    }
+
+    #[test]
+    fn term_weeks() {
+        // starts on a Tuesday and ends on a Sunday
+        let autumn = weeks(Term {
+            year: 2023,
+            season: Season::Autumn,
+        });
+        assert_eq!(autumn, (31..=52).map(|w| (2023, w)).collect::<Vec<_>>());
+
+        // starts in 2020-W53
+        let spring = weeks(Term {
+            year: 2021,
+            season: Season::Spring,
+        });
+        assert_eq!(spring.len(), 31);
+        assert_eq!(spring[0], (2020, 53));
+        assert_eq!(spring[1], (2021, 1));
+        assert_eq!(spring[30], (2021, 30));
+
+        // ends in 2025-W01
+        let autumn = weeks(Term {
+            year: 2024,
+            season: Season::Autumn,
+        });
+        assert_eq!(autumn.len(), 23);
+        assert_eq!(autumn[0], (2024, 31));
+        assert_eq!(autumn[21], (2024, 52));
+        assert_eq!(autumn[22], (2025, 1));
+    }
+
+    #[test]
+    fn deserialize_sorted() {
+        let lesson = |hour| Lesson {
+            teachers: Vec::new(),
+            locations: Vec::new(),
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, hour, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, hour + 1, 0, 0).unwrap(),
+            course: None,
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+
+        let json = serde_json::to_string(&[lesson(10), lesson(8)]).unwrap();
+        let schedule: Schedule = serde_json::from_str(&json).unwrap();
+        assert_eq!(schedule.into_inner(), [lesson(8), lesson(10)]);
+    }
+
+    #[test]
+    fn course_totals_across_new_year() {
+        let lesson = |d: NaiveDate| Lesson {
+            teachers: Vec::new(),
+            locations: Vec::new(),
+            group: None,
+            start: Utc.from_utc_datetime(&d.and_hms_opt(8, 0, 0).unwrap()),
+            end: Utc.from_utc_datetime(&d.and_hms_opt(9, 0, 0).unwrap()),
+            course: Some("Matematik".to_owned()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+
+        let schedule = Schedule::new(vec![
+            lesson(date(2020, 12, 31)),
+            lesson(date(2021, 1, 1)),
+            lesson(date(2021, 1, 4)),
+        ]);
+        let totals = schedule.course_totals(&Utc);
+
+        assert_eq!(totals.len(), 1);
+        let weeks = totals[0]
+            .weeks
+            .iter()
+            .map(|w| (w.year, w.week, w.minutes))
+            .collect::<Vec<_>>();
+        assert_eq!(weeks, [(2020, 53, 120), (2021, 1, 60)]);
+        let terms = totals[0]
+            .terms
+            .iter()
+            .map(|t| (t.term.year, t.term.season, t.minutes))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            terms,
+            [(2020, Season::Autumn, 60), (2021, Season::Spring, 120)]
+        );
+    }
+}
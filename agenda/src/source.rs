@@ -0,0 +1,102 @@
+//! Combining lessons from several places into one agenda.
+
+use std::{borrow::Cow, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use csscolorparser::Color;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{LessonLike, LessonSer};
+
+/// Where a set of lessons comes from, e.g. a child or a personal calendar.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Source {
+    pub name: String,
+    /// Replaces the color of every lesson from this source.
+    pub color: Option<Color>,
+    /// Prepended to every course name, e.g. `"Alice: "`.
+    pub prefix: Option<String>,
+}
+
+/// A lesson tagged with its [`Source`].
+#[derive(Debug, Clone)]
+pub struct Tagged<T> {
+    pub lesson: T,
+    pub source: Arc<Source>,
+}
+
+impl<T: LessonLike> LessonLike for Tagged<T> {
+    fn teachers(&self) -> Vec<Cow<str>> {
+        self.lesson.teachers()
+    }
+
+    fn locations(&self) -> Vec<Cow<str>> {
+        self.lesson.locations()
+    }
+
+    fn group(&self) -> Option<Cow<str>> {
+        self.lesson.group()
+    }
+
+    fn start(&self) -> DateTime<Utc> {
+        self.lesson.start()
+    }
+
+    fn end(&self) -> DateTime<Utc> {
+        self.lesson.end()
+    }
+
+    fn course(&self) -> Option<Cow<str>> {
+        match (&self.source.prefix, self.lesson.course()) {
+            (Some(prefix), Some(course)) => Some(format!("{prefix}{course}").into()),
+            (_, course) => course,
+        }
+    }
+
+    /// The same lesson can appear in several sources (e.g. siblings in the
+    /// same class), so the id is namespaced by the source name.
+    fn id(&self) -> Uuid {
+        Uuid::new_v5(&self.lesson.id(), self.source.name.as_bytes())
+    }
+
+    fn series(&self) -> Option<Uuid> {
+        self.lesson
+            .series()
+            .map(|series| Uuid::new_v5(&series, self.source.name.as_bytes()))
+    }
+
+    fn color(&self) -> Option<&Color> {
+        self.source.color.as_ref().or_else(|| self.lesson.color())
+    }
+
+    fn source(&self) -> Option<Cow<str>> {
+        Some(self.source.name.as_str().into())
+    }
+}
+
+impl<T: LessonLike> Serialize for Tagged<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        LessonSer::new(self).serialize(serializer)
+    }
+}
+
+/// Merge lessons from several sources into one list, sorted by start time.
+pub fn merge<T: LessonLike>(sources: impl IntoIterator<Item = (Source, Vec<T>)>) -> Vec<Tagged<T>> {
+    let mut out = sources
+        .into_iter()
+        .flat_map(|(source, lessons)| {
+            let source = Arc::new(source);
+            lessons.into_iter().map(move |lesson| Tagged {
+                lesson,
+                source: source.clone(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    out.sort_by_key(|l| (l.start(), l.end()));
+    out
+}
@@ -0,0 +1,251 @@
+//! Timetable rendering.
+
+use std::{collections::HashMap, fmt::Write};
+
+use chrono::{Datelike, IsoWeek, NaiveDate, TimeZone, Timelike, Weekday};
+use csscolorparser::Color;
+use uuid::Uuid;
+
+use crate::{conflicts, export::escape_xml, LessonLike, Render, Schedule};
+
+const WIDTH: f64 = 1000.0;
+const HEADER_HEIGHT: f64 = 32.0;
+const AXIS_WIDTH: f64 = 48.0;
+const HOUR_HEIGHT: f64 = 60.0;
+const PADDING: f64 = 2.0;
+const FONT_SIZE: f64 = 12.0;
+
+const DEFAULT_COLOR: &str = "#d0d0d0";
+
+/// Black or white, whichever is more readable on top of `background`.
+fn text_color(background: &Color) -> &'static str {
+    let luminance = 0.2126 * background.r + 0.7152 * background.g + 0.0722 * background.b;
+    if luminance > 0.5 {
+        "#000"
+    } else {
+        "#fff"
+    }
+}
+
+/// Minutes since local midnight.
+fn minute_of_day<Tz: TimeZone>(time: chrono::DateTime<Tz>) -> u32 {
+    time.hour() * 60 + time.minute()
+}
+
+/// Draw `week` as an SVG timetable with one column per day. Weekends are only
+/// included if they contain lessons, and overlapping lessons are drawn side by
+/// side. Lessons are labelled with their summary and location.
+pub fn week_svg<T: LessonLike, Tz: TimeZone>(
+    lessons: &[T],
+    week: IsoWeek,
+    render: &Render,
+    tz: &Tz,
+) -> String {
+    let schedule = Schedule::new(lessons.iter().collect());
+    let days = schedule.by_day(tz);
+
+    let mut weekdays = vec![
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+    ];
+    for weekend in [Weekday::Sat, Weekday::Sun] {
+        let date = NaiveDate::from_isoywd_opt(week.year(), week.week(), weekend);
+        if matches!(date, Some(d) if days.contains_key(&d)) {
+            weekdays.push(weekend);
+        }
+    }
+
+    let in_week = || {
+        days.iter()
+            .filter(|(date, _)| {
+                let w = date.iso_week();
+                (w.year(), w.week()) == (week.year(), week.week())
+            })
+            .flat_map(|(_, lessons)| lessons)
+    };
+
+    // show at least 08:00–17:00
+    let first_hour = in_week()
+        .map(|l| l.start().with_timezone(tz).hour())
+        .min()
+        .unwrap_or(8)
+        .min(8);
+    let last_hour = in_week()
+        .map(|l| {
+            let end = l.end().with_timezone(tz);
+            end.hour() + u32::from(end.minute() > 0)
+        })
+        .max()
+        .unwrap_or(17)
+        .clamp(17, 24);
+
+    let hours = last_hour - first_hour;
+    let height = HEADER_HEIGHT + f64::from(hours) * HOUR_HEIGHT;
+    let day_width = (WIDTH - AXIS_WIDTH) / weekdays.len() as f64;
+    let y = |minute: u32| {
+        HEADER_HEIGHT + f64::from(minute.saturating_sub(first_hour * 60)) * HOUR_HEIGHT / 60.0
+    };
+
+    let mut out = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{height}" viewBox="0 0 {WIDTH} {height}" font-family="sans-serif" font-size="{FONT_SIZE}">"#
+    );
+    out.push_str(r##"<rect width="100%" height="100%" fill="#fff"/>"##);
+
+    for hour in first_hour..=last_hour {
+        let y = y(hour * 60);
+        let _ = write!(
+            out,
+            r##"<line x1="{AXIS_WIDTH}" y1="{y}" x2="{WIDTH}" y2="{y}" stroke="#e5e5e5"/>"##
+        );
+        if hour < last_hour {
+            let _ = write!(
+                out,
+                r##"<text x="{}" y="{}" text-anchor="end" fill="#666">{hour:02}:00</text>"##,
+                AXIS_WIDTH - 6.0,
+                y + FONT_SIZE
+            );
+        }
+    }
+
+    for (i, weekday) in weekdays.into_iter().enumerate() {
+        let x = AXIS_WIDTH + i as f64 * day_width;
+        let date = match NaiveDate::from_isoywd_opt(week.year(), week.week(), weekday) {
+            Some(date) => date,
+            None => continue,
+        };
+
+        let _ = write!(
+            out,
+            r##"<text x="{}" y="{}" text-anchor="middle" font-weight="bold">{} {}/{}</text>"##,
+            x + day_width / 2.0,
+            HEADER_HEIGHT - 10.0,
+            render.locale.weekday(weekday),
+            date.day(),
+            date.month()
+        );
+        let _ = write!(
+            out,
+            r##"<line x1="{x}" y1="0" x2="{x}" y2="{height}" stroke="#e5e5e5"/>"##
+        );
+
+        let lessons = match days.get(&date) {
+            Some(lessons) => lessons,
+            None => continue,
+        };
+
+        // column and number of columns of lessons that overlap others
+        let mut columns = HashMap::<Uuid, (usize, usize)>::new();
+        for conflict in conflicts(lessons) {
+            for placement in conflict.lessons {
+                columns.insert(placement.lesson.id(), (placement.column, conflict.columns));
+            }
+        }
+
+        for lesson in lessons {
+            let (column, n) = columns.get(&lesson.id()).copied().unwrap_or((0, 1));
+            let width = day_width / n as f64;
+            let x = x + column as f64 * width + PADDING;
+            let width = width - 2.0 * PADDING;
+            let top = y(minute_of_day(lesson.start().with_timezone(tz)));
+            let bottom = y(minute_of_day(lesson.end().with_timezone(tz)));
+            let height = (bottom - top).max(1.0);
+
+            let (fill, text) = match lesson.color() {
+                Some(color) => (color.to_hex_string(), text_color(color)),
+                None => (DEFAULT_COLOR.to_owned(), "#000"),
+            };
+
+            // a nested <svg> clips labels that don't fit
+            let _ = write!(
+                out,
+                r#"<svg x="{x}" y="{top}" width="{width}" height="{height}"><rect width="100%" height="100%" rx="3" fill="{fill}"/>"#
+            );
+
+            let labels = [
+                Some(render.summary(lesson)),
+                lesson.location().map(Into::into),
+            ];
+            for (line, label) in labels.into_iter().flatten().enumerate() {
+                let _ = write!(
+                    out,
+                    r#"<text x="4" y="{}" fill="{text}">{}</text>"#,
+                    (line + 1) as f64 * (FONT_SIZE + 2.0),
+                    escape_xml(&label)
+                );
+            }
+
+            out.push_str("</svg>");
+        }
+    }
+
+    out.push_str("</svg>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::week_svg;
+    use crate::{Lesson, Render};
+
+    fn lesson(id: u128, course: &str, start: u32, end: u32) -> Lesson {
+        Lesson {
+            teachers: Vec::new(),
+            locations: vec!["A1".into()],
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, start, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, end, 0, 0).unwrap(),
+            course: Some(course.into()),
+            id: Uuid::from_u128(id),
+            series: None,
+            color: None,
+            source: None,
+        }
+    }
+
+    fn week() -> chrono::IsoWeek {
+        NaiveDate::from_ymd_opt(2023, 1, 9).unwrap().iso_week()
+    }
+
+    #[test]
+    fn empty_week() {
+        let svg = week_svg::<Lesson, _>(&[], week(), &Render::default(), &Utc);
+
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>"));
+        // 08:00–17:00
+        assert!(svg.contains(r#"height="572""#));
+        for day in ["Mån 9/1", "Tis 10/1", "Ons 11/1", "Tor 12/1", "Fre 13/1"] {
+            assert!(svg.contains(day), "{day} is missing");
+        }
+        assert!(!svg.contains("Lör"));
+        assert!(!svg.contains("<rect width=\"100%\" height=\"100%\" rx="));
+    }
+
+    #[test]
+    fn escaping() {
+        let lessons = [lesson(1, "<b>Fysik</b> & \"kemi\"", 8, 9)];
+        let svg = week_svg(&lessons, week(), &Render::default(), &Utc);
+
+        assert!(svg.contains("&lt;b&gt;Fysik&lt;/b&gt; &amp; &quot;kemi&quot;"));
+        assert!(!svg.contains("<b>"));
+    }
+
+    #[test]
+    fn overlapping() {
+        let lessons = [lesson(1, "Fysik", 8, 10), lesson(2, "Kemi", 9, 11)];
+        let svg = week_svg(&lessons, week(), &Render::default(), &Utc);
+
+        // each lesson gets half of Monday's column
+        let day_width = (super::WIDTH - super::AXIS_WIDTH) / 5.0;
+        let width = day_width / 2.0 - 2.0 * super::PADDING;
+        assert_eq!(svg.matches(&format!(r#"width="{width}""#)).count(), 2);
+        assert!(svg.contains("Fysik"));
+        assert!(svg.contains("Kemi"));
+    }
+}
@@ -4,7 +4,7 @@
 
 use std::collections::HashMap;
 
-use chrono::{IsoWeek, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono::{Datelike, IsoWeek, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
 use chrono_tz::{Europe::Stockholm, Tz};
 use csscolorparser::Color;
 use reqwest::header::HeaderValue;
@@ -125,7 +125,8 @@ struct Lesson {
     time_start: String,
     time_end: String,
     day_of_week_number: u8,
-    // block_name: String,
+    #[serde(default)]
+    block_name: Option<String>,
 }
 
 impl Lesson {
@@ -155,9 +156,13 @@ impl Lesson {
 
         let mut texts = self.texts.into_iter().filter(|s| !s.is_empty());
         let course = texts.next();
-        // `texts` is sometimes [course, location] and sometimes [course, teacher, location]
-        let location = texts.next_back();
-        let teacher = texts.next();
+        // `texts` is sometimes [course, location] and sometimes [course, teacher, location],
+        // where both teachers and locations can be comma separated lists
+        let locations = texts
+            .next_back()
+            .map(|s| split_list(&s))
+            .unwrap_or_default();
+        let teachers = texts.next().map(|s| split_list(&s)).unwrap_or_default();
 
         let start = Lesson::TZ
             .from_local_datetime(&date.and_time(start))
@@ -179,18 +184,62 @@ impl Lesson {
             start,
             end,
             course,
-            teacher,
-            location,
+            teachers,
+            locations,
+            group: self.block_name.filter(|s| !s.is_empty()),
             id: Uuid::new_v5(&UUID_NAMESPACE, &characteristic),
+            series: None,
             color,
+            source: None,
         })
     }
 }
 
+fn split_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
 const UUID_NAMESPACE: Uuid = Uuid::from_bytes([
     0x66, 0x2c, 0x31, 0x31, 0xb1, 0x81, 0x40, 0xdc, 0x88, 0xb4, 0x05, 0x2b, 0x18, 0xce, 0x53, 0x4b,
 ]);
 
+/// Assign [`skool_agenda::Lesson::series`] to a week of lessons.
+///
+/// A series is identified by course, group (block), weekday and the order
+/// among lessons sharing all of those on that day. Start and end times are
+/// deliberately left out, so moving a lesson within the day keeps its series,
+/// and so are teachers, so that a substitute doesn't start a new series.
+fn assign_series(lessons: &mut [skool_agenda::Lesson]) {
+    lessons.sort_by_key(|lesson| lesson.start);
+
+    let mut ordinals = HashMap::new();
+
+    for lesson in lessons.iter_mut() {
+        let weekday = lesson.start.with_timezone(&Lesson::TZ).weekday();
+        let fields = [
+            lesson.course.as_deref().unwrap_or_default(),
+            lesson.group.as_deref().unwrap_or_default(),
+        ]
+        .join("\0");
+
+        let ordinal: &mut u32 = ordinals.entry((weekday, fields.clone())).or_default();
+        let characteristic = [
+            &b"series\0"[..],
+            fields.as_bytes(),
+            &weekday.number_from_monday().to_be_bytes()[..],
+            &ordinal.to_be_bytes()[..],
+        ]
+        .concat();
+        *ordinal += 1;
+
+        lesson.series = Some(Uuid::new_v5(&UUID_NAMESPACE, &characteristic));
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Box {
@@ -320,7 +369,7 @@ pub async fn lessons_by_week(
         })
         .collect();
 
-    let lessons = data
+    let mut lessons = data
         .lesson_info
         .unwrap_or_default()
         .into_iter()
@@ -333,6 +382,8 @@ pub async fn lessons_by_week(
         })
         .collect::<Vec<_>>();
 
+    assign_series(&mut lessons);
+
     debug!("found {} lessons", lessons.len());
 
     Ok(lessons)
@@ -495,11 +546,12 @@ mod tests {
     use std::env;
 
     use async_once_cell::OnceCell;
-    use chrono::{Datelike, NaiveDate};
+    use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+    use uuid::Uuid;
 
     use crate::client::Client;
 
-    use super::{lessons_by_week, Selection};
+    use super::{assign_series, lessons_by_week, Selection};
 
     async fn client() -> Client {
         static CLIENT: OnceCell<Client> = OnceCell::new();
@@ -548,4 +600,42 @@ mod tests {
 
         assert!(!lessons.is_empty());
     }
+
+    #[test]
+    fn series() {
+        let lesson = |day, hour, course: &str| skool_agenda::Lesson {
+            teachers: vec!["ABC".to_owned()],
+            locations: Vec::new(),
+            group: Some("MA1C-1".to_owned()),
+            start: Utc.with_ymd_and_hms(2023, 1, day, hour, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, day, hour + 1, 0, 0).unwrap(),
+            course: Some(course.to_owned()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+        let series = |lessons: &[skool_agenda::Lesson]| {
+            lessons
+                .iter()
+                .map(|l| l.series.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let mut a = [lesson(9, 8, "Ma"), lesson(9, 12, "Ma"), lesson(10, 8, "Ma")];
+        // the week after, with lessons moved around within each day
+        let mut b = [
+            lesson(16, 13, "Ma"),
+            lesson(16, 9, "Ma"),
+            lesson(17, 10, "Ma"),
+        ];
+        // and a substitute teacher
+        b[2].teachers = vec!["XYZ".to_owned()];
+        assign_series(&mut a);
+        assign_series(&mut b);
+
+        assert_eq!(series(&a), series(&b));
+        assert_ne!(series(&a)[0], series(&a)[1]);
+        assert_ne!(series(&a)[0], series(&a)[2]);
+    }
 }
@@ -0,0 +1,4 @@
+ALTER TABLE
+  links DROP COLUMN include_courses,
+  DROP COLUMN exclude_courses,
+  DROP COLUMN course_pattern;
@@ -0,0 +1,8 @@
+ALTER TABLE
+  links
+ADD
+  COLUMN include_courses TEXT[],
+ADD
+  COLUMN exclude_courses TEXT[] NOT NULL DEFAULT '{}',
+ADD
+  COLUMN course_pattern TEXT;
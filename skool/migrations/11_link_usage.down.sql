@@ -0,0 +1,5 @@
+DROP TABLE link_accesses;
+
+ALTER TABLE
+  links DROP COLUMN label,
+  DROP COLUMN access_count;
@@ -0,0 +1,16 @@
+ALTER TABLE
+  links
+ADD
+  COLUMN label TEXT,
+ADD
+  COLUMN access_count BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE link_accesses (
+  link BYTEA NOT NULL REFERENCES links(id) ON DELETE CASCADE,
+  accessed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  format TEXT NOT NULL
+);
+
+CREATE INDEX link_accesses_link_idx ON link_accesses (link, accessed_at);
+
+CREATE INDEX link_accesses_accessed_at_idx ON link_accesses (accessed_at);
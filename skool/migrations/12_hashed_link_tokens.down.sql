@@ -0,0 +1,13 @@
+-- hashed tokens can't be recovered, so those links are lost
+DELETE FROM
+  links
+WHERE
+  prefix IS NOT NULL;
+
+ALTER TABLE
+  link_accesses DROP CONSTRAINT link_accesses_link_fkey,
+ADD
+  CONSTRAINT link_accesses_link_fkey FOREIGN KEY (link) REFERENCES links(id) ON DELETE CASCADE;
+
+ALTER TABLE
+  links DROP COLUMN prefix;
@@ -0,0 +1,11 @@
+-- existing links still have plaintext tokens as ids (and no prefix), and are
+-- hashed by the server at startup since the key isn't known here
+ALTER TABLE
+  links
+ADD
+  COLUMN prefix TEXT;
+
+ALTER TABLE
+  link_accesses DROP CONSTRAINT link_accesses_link_fkey,
+ADD
+  CONSTRAINT link_accesses_link_fkey FOREIGN KEY (link) REFERENCES links(id) ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,4 @@
+ALTER TABLE
+  credentials
+ADD
+  COLUMN lent_at TIMESTAMPTZ;
@@ -0,0 +1,4 @@
+ALTER TABLE
+  credentials
+ADD
+  COLUMN lend_to_class BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,43 @@
+DELETE FROM
+  credentials
+WHERE
+  slot != 'default';
+
+-- links to other slots would share the default one, so they are removed
+DELETE FROM
+  links
+WHERE
+  slot != 'default';
+
+ALTER TABLE
+  links DROP COLUMN slot;
+
+ALTER TABLE
+  schedule_cache DROP CONSTRAINT schedule_cache_owner_fkey,
+  DROP CONSTRAINT schedule_cache_pkey,
+  DROP COLUMN slot;
+
+ALTER TABLE
+  sync_status DROP CONSTRAINT sync_status_owner_fkey,
+  DROP CONSTRAINT sync_status_pkey,
+  DROP COLUMN slot;
+
+ALTER TABLE
+  credentials DROP CONSTRAINT credentials_pkey,
+  DROP COLUMN slot,
+ADD
+  PRIMARY KEY (uid);
+
+ALTER TABLE
+  schedule_cache
+ADD
+  PRIMARY KEY (owner, year, week),
+ADD
+  CONSTRAINT schedule_cache_owner_fkey FOREIGN KEY (owner) REFERENCES credentials(uid) ON DELETE CASCADE;
+
+ALTER TABLE
+  sync_status
+ADD
+  PRIMARY KEY (owner),
+ADD
+  CONSTRAINT sync_status_owner_fkey FOREIGN KEY (owner) REFERENCES credentials(uid) ON DELETE CASCADE;
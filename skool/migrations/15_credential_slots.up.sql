@@ -0,0 +1,40 @@
+ALTER TABLE
+  schedule_cache DROP CONSTRAINT schedule_cache_owner_fkey,
+  DROP CONSTRAINT schedule_cache_pkey;
+
+ALTER TABLE
+  sync_status DROP CONSTRAINT sync_status_owner_fkey,
+  DROP CONSTRAINT sync_status_pkey;
+
+ALTER TABLE
+  credentials
+ADD
+  COLUMN slot VARCHAR(32) NOT NULL DEFAULT 'default',
+  DROP CONSTRAINT credentials_pkey,
+ADD
+  PRIMARY KEY (uid, slot);
+
+ALTER TABLE
+  schedule_cache
+ADD
+  COLUMN slot VARCHAR(32) NOT NULL DEFAULT 'default',
+ADD
+  PRIMARY KEY (owner, slot, year, week),
+ADD
+  CONSTRAINT schedule_cache_owner_fkey FOREIGN KEY (owner, slot) REFERENCES credentials(uid, slot) ON DELETE CASCADE;
+
+ALTER TABLE
+  sync_status
+ADD
+  COLUMN slot VARCHAR(32) NOT NULL DEFAULT 'default',
+ADD
+  PRIMARY KEY (owner, slot),
+ADD
+  CONSTRAINT sync_status_owner_fkey FOREIGN KEY (owner, slot) REFERENCES credentials(uid, slot) ON DELETE CASCADE;
+
+-- existing links were made when users had a single account, so they keep
+-- sharing that one
+ALTER TABLE
+  links
+ADD
+  COLUMN slot VARCHAR(32) NOT NULL DEFAULT 'default';
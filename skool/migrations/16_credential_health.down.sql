@@ -0,0 +1,7 @@
+ALTER TABLE
+  credentials DROP COLUMN status,
+  DROP COLUMN last_login_at,
+  DROP COLUMN last_failure_at,
+  DROP COLUMN failure_reason;
+
+DROP TYPE credential_status;
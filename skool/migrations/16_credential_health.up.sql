@@ -0,0 +1,18 @@
+CREATE TYPE credential_status AS ENUM (
+  'unknown',
+  'ok',
+  'invalid',
+  'failing',
+  'unreadable'
+);
+
+ALTER TABLE
+  credentials
+ADD
+  COLUMN status credential_status NOT NULL DEFAULT 'unknown',
+ADD
+  COLUMN last_login_at TIMESTAMPTZ,
+ADD
+  COLUMN last_failure_at TIMESTAMPTZ,
+ADD
+  COLUMN failure_reason TEXT;
@@ -0,0 +1 @@
+DROP TABLE class_schedule_cache;
@@ -0,0 +1,9 @@
+CREATE TABLE class_schedule_cache (
+  school BYTEA NOT NULL,
+  reference VARCHAR(255) NOT NULL,
+  year INTEGER NOT NULL,
+  week INTEGER NOT NULL,
+  fetched_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  data BYTEA NOT NULL,
+  PRIMARY KEY (school, reference, year, week)
+);
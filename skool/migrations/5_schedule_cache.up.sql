@@ -0,0 +1,8 @@
+CREATE TABLE schedule_cache (
+  owner UUID NOT NULL REFERENCES credentials(uid) ON DELETE CASCADE,
+  year INTEGER NOT NULL,
+  week INTEGER NOT NULL,
+  fetched_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  data BYTEA NOT NULL,
+  PRIMARY KEY (owner, year, week)
+);
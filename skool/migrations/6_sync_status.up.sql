@@ -0,0 +1,4 @@
+CREATE TABLE sync_status (
+  owner UUID PRIMARY KEY REFERENCES credentials(uid) ON DELETE CASCADE,
+  succeeded_at TIMESTAMPTZ
+);
@@ -0,0 +1,27 @@
+CREATE TABLE webhooks (
+  id UUID PRIMARY KEY,
+  owner UUID NOT NULL,
+  url TEXT NOT NULL,
+  -- encrypted and bound to the id
+  secret BYTEA NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX webhooks_owner_idx ON webhooks (owner);
+
+CREATE TABLE webhook_deliveries (
+  id UUID PRIMARY KEY,
+  webhook UUID NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+  payload TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  attempts INTEGER NOT NULL DEFAULT 0,
+  -- NULL once delivered or given up on
+  next_attempt_at TIMESTAMPTZ DEFAULT NOW(),
+  delivered_at TIMESTAMPTZ,
+  last_status INTEGER,
+  last_error TEXT
+);
+
+CREATE INDEX webhook_deliveries_due_idx ON webhook_deliveries (next_attempt_at)
+WHERE
+  next_attempt_at IS NOT NULL;
@@ -0,0 +1,11 @@
+CREATE TABLE push_subscriptions (
+  id UUID PRIMARY KEY,
+  owner UUID NOT NULL,
+  endpoint TEXT NOT NULL UNIQUE,
+  p256dh BYTEA NOT NULL,
+  auth BYTEA NOT NULL,
+  locale TEXT NOT NULL DEFAULT 'sv',
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX push_subscriptions_owner_idx ON push_subscriptions (owner);
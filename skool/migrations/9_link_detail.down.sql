@@ -0,0 +1,4 @@
+ALTER TABLE
+  links DROP COLUMN detail;
+
+DROP TYPE link_detail;
@@ -0,0 +1,6 @@
+CREATE TYPE link_detail AS ENUM ('full', 'course_only', 'busy_free');
+
+ALTER TABLE
+  links
+ADD
+  COLUMN detail link_detail NOT NULL DEFAULT 'full';
@@ -1,11 +1,20 @@
-use std::array::TryFromSliceError;
+use std::{array::TryFromSliceError, iter};
 
+use chrono::{Datelike, IsoWeek, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{Postgres, Transaction};
-use tracing::error;
+use skool_agenda::Lesson;
+use sqlx::{PgExecutor, Postgres, Transaction};
+use tracing::{debug, error, instrument, warn};
 use uuid::Uuid;
 
-use crate::{error::AppError, session::Session, Result, System};
+use crate::{
+    credentials::{self, Slot},
+    crypt::{decrypt_bytes_aad, encrypt_bytes_aad},
+    error::AppError,
+    lessons,
+    session::{self, Session},
+    AppState, Result, System, TIMEZONE,
+};
 
 #[derive(Debug, Clone, Serialize, sqlx::FromRow)]
 pub struct Class {
@@ -68,6 +77,7 @@ pub async fn from_session(session: Session) -> Result<Class> {
 pub async fn add_to_class<'a>(
     class: &Class,
     uid: Uuid,
+    slot: &Slot,
     tx: &mut Transaction<'a, Postgres>,
 ) -> Result<()> {
     sqlx::query!(
@@ -84,13 +94,210 @@ pub async fn add_to_class<'a>(
     .await?;
 
     sqlx::query!(
-        "UPDATE credentials SET (school, class_reference) = ($1, $2) WHERE uid = $3",
+        "UPDATE credentials SET (school, class_reference) = ($1, $2) WHERE uid = $3 AND slot = $4",
         class.school.as_ref(),
         class.reference,
-        uid
+        uid,
+        slot.as_str()
     )
     .execute(tx)
     .await?;
 
     Ok(())
 }
+
+/// Linked accounts in the class whose owners have agreed to lend them, least
+/// recently lent first. Credentials that were rejected are left out, and those
+/// that failed otherwise (including ones that couldn't be decrypted) are left
+/// out for a while, so that they are tried again once fixed.
+pub async fn members(
+    school: &SchoolHash,
+    class: &str,
+    db: impl PgExecutor<'_>,
+) -> Result<Vec<(Uuid, Slot)>> {
+    let members = sqlx::query!(
+        r#"
+          SELECT uid, slot AS "slot: Slot" FROM credentials
+          WHERE school = $1 AND class_reference = $2 AND lend_to_class
+            AND status != 'invalid'
+            AND (last_failure_at IS NULL OR last_failure_at < NOW() - INTERVAL '6 hours')
+          ORDER BY lent_at ASC NULLS FIRST
+        "#,
+        school.as_ref(),
+        class
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(members.into_iter().map(|r| (r.uid, r.slot)).collect())
+}
+
+/// Get the lessons of a class, as shown to the whole class rather than to any
+/// one student. Weeks are cached per class like the lessons of users (see
+/// [`lessons::CacheConfig`]), and stale weeks are fetched with the
+/// requester's session if possible, otherwise with the credentials of another
+/// member who lends them (see [`members`]). Members take turns, and if
+/// someone's credentials fail they are marked as such and the next member is
+/// tried.
+#[instrument(skip(weeks, ctx))]
+pub async fn lessons(
+    school: &SchoolHash,
+    class: &str,
+    requester: (Uuid, Slot),
+    weeks: impl IntoIterator<Item = IsoWeek>,
+    ctx: &AppState,
+) -> Result<Vec<Lesson>> {
+    let weeks = weeks.into_iter().collect::<Vec<_>>();
+    let (mut lessons, stale) = cached(school, class, &weeks, ctx).await?;
+
+    debug!(stale = stale.len(), "class cache lookup done");
+
+    if stale.is_empty() {
+        return Ok(lessons);
+    }
+
+    let members = members(school, class, &ctx.postgres).await?;
+    let mut last_error = AppError::NotFound("class not found");
+
+    let others = members.into_iter().filter(|m| *m != requester);
+
+    for (uid, slot) in iter::once(requester.clone()).chain(others) {
+        match fetch_as(uid, &slot, class, &stale, ctx).await {
+            Ok(fetched) => {
+                if uid != requester.0 {
+                    sqlx::query!(
+                        "UPDATE credentials SET lent_at = NOW() WHERE uid = $1 AND slot = $2",
+                        uid,
+                        slot.as_str()
+                    )
+                    .execute(&ctx.postgres)
+                    .await?;
+                }
+
+                for (week, fetched) in fetched {
+                    store(school, class, week, &fetched, ctx).await?;
+                    lessons.extend(fetched);
+                }
+
+                return Ok(lessons);
+            }
+            Err(
+                e @ (AppError::BadCredentials
+                | AppError::ScrapingFailed
+                | AppError::TimetableNotFound
+                | AppError::MissingCredentials),
+            ) => {
+                warn!(%uid, %slot, error = %e, "credentials failed for class lookup");
+
+                credentials::record_error(uid, &slot, &e.to_string(), &ctx.postgres).await?;
+
+                last_error = e;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(last_error)
+}
+
+async fn fetch_as(
+    uid: Uuid,
+    slot: &Slot,
+    class: &str,
+    weeks: &[IsoWeek],
+    ctx: &AppState,
+) -> Result<Vec<(IsoWeek, Vec<Lesson>)>> {
+    let session = session::get(uid, slot, ctx)
+        .await?
+        .ok_or(AppError::MissingCredentials)?;
+
+    lessons::fetch(session, Some(class), weeks.iter().copied()).await
+}
+
+/// Associated data of a cached week, which binds it to the class and week.
+fn aad(school: &SchoolHash, class: &str, week: IsoWeek) -> Vec<u8> {
+    [
+        &b"class lessons\0"[..],
+        school.as_ref(),
+        &week.year().to_be_bytes(),
+        &week.week().to_be_bytes(),
+        class.as_bytes(),
+    ]
+    .concat()
+}
+
+/// The cached lessons of the weeks that are still fresh, and the weeks that
+/// aren't.
+async fn cached(
+    school: &SchoolHash,
+    class: &str,
+    weeks: &[IsoWeek],
+    ctx: &AppState,
+) -> Result<(Vec<Lesson>, Vec<IsoWeek>)> {
+    let (years, numbers): (Vec<i32>, Vec<i32>) =
+        weeks.iter().map(|w| (w.year(), w.week() as i32)).unzip();
+
+    let cached = sqlx::query!(
+        r#"
+          SELECT year, week, fetched_at, data FROM class_schedule_cache
+          WHERE school = $1 AND reference = $2
+            AND (year, week) IN (SELECT * FROM UNNEST($3::INTEGER[], $4::INTEGER[]))
+        "#,
+        school.as_ref(),
+        class,
+        &years,
+        &numbers,
+    )
+    .fetch_all(&ctx.postgres)
+    .await?;
+
+    let now = Utc::now();
+    let today = now.with_timezone(&TIMEZONE).date_naive();
+    let mut lessons = Vec::new();
+    let mut stale = Vec::new();
+
+    for &week in weeks {
+        let record = cached
+            .iter()
+            .find(|r| r.year == week.year() && r.week == week.week() as i32)
+            .filter(|r| now - r.fetched_at < ctx.config.cache.ttl(week, today));
+
+        let aad = aad(school, class, week);
+        match record.map(|r| decrypt_bytes_aad::<Vec<Lesson>>(&r.data, &aad, &ctx.keyring())) {
+            Some(Ok(cached)) => lessons.extend(cached),
+            Some(Err(e)) => {
+                warn!(error = %e, "failed to decrypt cached class lessons");
+                stale.push(week);
+            }
+            None => stale.push(week),
+        }
+    }
+
+    Ok((lessons, stale))
+}
+
+async fn store(
+    school: &SchoolHash,
+    class: &str,
+    week: IsoWeek,
+    lessons: &[Lesson],
+    ctx: &AppState,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+          INSERT INTO class_schedule_cache (school, reference, year, week, data)
+          VALUES ($1, $2, $3, $4, $5)
+          ON CONFLICT (school, reference, year, week) DO UPDATE
+            SET (fetched_at, data) = (NOW(), EXCLUDED.data)
+        "#,
+        school.as_ref(),
+        class,
+        week.year(),
+        week.week() as i32,
+        encrypt_bytes_aad(&lessons, &aad(school, class, week), &ctx.keyring())?,
+    )
+    .execute(&ctx.postgres)
+    .await?;
+
+    Ok(())
+}
@@ -1,16 +1,26 @@
-use aes_gcm_siv::{Aes256GcmSiv, Key};
+use std::{collections::HashMap, fmt};
+
 use auth1_sdk::Identity;
-use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    http::request::Parts,
+};
 use chrono::{DateTime, Utc};
 
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 
 use sqlx::PgExecutor;
-use tracing::{error, instrument};
+use tracing::{error, info, instrument};
 use uuid::Uuid;
 
-use crate::{class::SchoolHash, crypt::decrypt_bytes, error::AppError, AppState, Result};
+use crate::{
+    class::SchoolHash,
+    crypt::{self, decrypt_bytes_aad, reencrypt as reencrypt_bytes, Keyring},
+    error::AppError,
+    AppState, Result,
+};
 
 fn serialize_secret<S>(secret: &SecretString, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -46,75 +56,321 @@ impl From<Private> for Public {
     }
 }
 
+/// Name of one of a user's linked accounts, e.g. one per child. `all` can't
+/// be used, since `/credentials/all` lists the accounts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(try_from = "String")]
+#[sqlx(transparent)]
+pub struct Slot(String);
+
+impl Slot {
+    pub const DEFAULT: &'static str = "default";
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Self(Self::DEFAULT.to_owned())
+    }
+}
+
+impl TryFrom<String> for Slot {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let valid = s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if (1..=32).contains(&s.len()) && valid && s != "all" {
+            Ok(Self(s))
+        } else {
+            Err("invalid slot")
+        }
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Taken from the `slot` path parameter, or the default slot if the route has
+/// none.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Slot {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let params = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map(|Path(params)| params)
+            .unwrap_or_default();
+
+        match params.get("slot") {
+            Some(slot) => Slot::try_from(slot.clone()).map_err(AppError::BadRequest),
+            None => Ok(Slot::default()),
+        }
+    }
+}
+
+/// Whether the credentials work, as of the last login with them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "credential_status", rename_all = "snake_case")]
+pub enum Status {
+    /// Not used since health tracking was added.
+    #[default]
+    Unknown,
+    Ok,
+    /// Rejected by the school. The user has to log in again.
+    Invalid,
+    /// The last login failed for some other reason, which is probably
+    /// temporary.
+    Failing,
+    /// Can't be decrypted, e.g. because the key was removed. Reset by the next
+    /// login, so either the user logs in again or the key is restored.
+    Unreadable,
+}
+
+#[derive(Debug, Clone, Default, Serialize, sqlx::FromRow)]
+pub struct Health {
+    pub status: Status,
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub last_failure_at: Option<DateTime<Utc>>,
+    pub failure_reason: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Credentials {
+    pub slot: Slot,
     pub updated_at: DateTime<Utc>,
     pub class: Option<String>,
     pub school: Option<SchoolHash>,
+    /// Whether classmates may look up the class schedule with these
+    /// credentials.
+    pub lend_to_class: bool,
+    pub health: Health,
     pub private: Private,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct PublicCredentials {
+    pub slot: Slot,
     pub updated_at: DateTime<Utc>,
     pub class: Option<String>,
     pub school: Option<SchoolHash>,
+    pub lend_to_class: bool,
+    #[serde(flatten)]
+    pub health: Health,
+    /// `None` if the credentials can't be decrypted.
     #[serde(flatten)]
-    pub public: Public,
+    pub public: Option<Public>,
 }
 
 impl From<Credentials> for PublicCredentials {
     fn from(c: Credentials) -> Self {
         let Credentials {
+            slot,
             updated_at,
             private,
             class,
             school,
+            lend_to_class,
+            health,
         } = c;
 
         Self {
+            slot,
             updated_at,
             class,
             school,
-            public: private.into(),
+            lend_to_class,
+            health,
+            public: Some(private.into()),
         }
     }
 }
 
-#[instrument(name = "get_credentials", skip(db, key))]
-pub async fn get(
-    user: Uuid,
-    db: impl PgExecutor<'_>,
-    key: &Key<Aes256GcmSiv>,
-) -> Result<Option<Credentials>> {
-    let record = match sqlx::query!(
-        "SELECT updated_at, data, school, class_reference FROM credentials WHERE uid = $1",
-        user
-    )
-    .fetch_optional(db)
-    .await?
-    {
-        Some(record) => record,
-        None => return Ok(None),
-    };
+/// Associated data of encrypted credentials, which binds them to their owner
+/// and slot.
+pub fn aad(uid: Uuid, slot: &Slot) -> Vec<u8> {
+    [
+        &b"credentials\0"[..],
+        uid.as_bytes(),
+        slot.as_str().as_bytes(),
+    ]
+    .concat()
+}
+
+#[derive(sqlx::FromRow)]
+struct Row {
+    uid: Uuid,
+    slot: Slot,
+    updated_at: DateTime<Utc>,
+    data: Vec<u8>,
+    school: Option<Vec<u8>>,
+    class_reference: Option<String>,
+    lend_to_class: bool,
+    #[sqlx(flatten)]
+    health: Health,
+}
 
-    let class = record.class_reference;
-    let school = record.school.and_then(|v| v.as_slice().try_into().ok());
+impl Row {
+    fn private(&self, keys: &Keyring) -> Result<Private, crypt::Error> {
+        decrypt_bytes_aad(&self.data, &aad(self.uid, &self.slot), keys)
+    }
 
-    match decrypt_bytes(&record.data, key) {
-        Ok(private) => Ok(Some(Credentials {
-            updated_at: record.updated_at,
-            class,
-            school,
+    fn into_credentials(self, private: Private) -> Credentials {
+        Credentials {
+            slot: self.slot,
+            updated_at: self.updated_at,
+            class: self.class_reference,
+            school: self.school.and_then(|v| v.as_slice().try_into().ok()),
+            lend_to_class: self.lend_to_class,
+            health: self.health,
             private,
-        })),
+        }
+    }
+
+    fn into_unreadable(self) -> PublicCredentials {
+        PublicCredentials {
+            slot: self.slot,
+            updated_at: self.updated_at,
+            class: self.class_reference,
+            school: self.school.and_then(|v| v.as_slice().try_into().ok()),
+            lend_to_class: self.lend_to_class,
+            health: Health {
+                status: Status::Unreadable,
+                ..self.health
+            },
+            public: None,
+        }
+    }
+}
+
+/// Credentials that can't be decrypted are recorded as [`Status::Unreadable`].
+#[instrument(name = "get_credentials", skip(ctx))]
+pub async fn get(user: Uuid, slot: &Slot, ctx: &AppState) -> Result<Option<Credentials>> {
+    let row: Option<Row> = sqlx::query_as("SELECT * FROM credentials WHERE uid = $1 AND slot = $2")
+        .bind(user)
+        .bind(slot.as_str())
+        .fetch_optional(&ctx.postgres)
+        .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    match row.private(&ctx.keyring()) {
+        Ok(private) => Ok(Some(row.into_credentials(private))),
         Err(e) => {
             error!("decrypt failed: {e}");
+
+            if row.health.status != Status::Unreadable {
+                record_failure(
+                    user,
+                    slot,
+                    Status::Unreadable,
+                    &e.to_string(),
+                    &ctx.postgres,
+                )
+                .await?;
+            }
+
             Ok(None)
         }
     }
 }
 
+/// All credentials of `user`, ordered by slot, including those that can't be
+/// decrypted.
+#[instrument(name = "list_credentials", skip(ctx))]
+pub async fn list(user: Uuid, ctx: &AppState) -> Result<Vec<PublicCredentials>> {
+    let rows: Vec<Row> = sqlx::query_as("SELECT * FROM credentials WHERE uid = $1 ORDER BY slot")
+        .bind(user)
+        .fetch_all(&ctx.postgres)
+        .await?;
+
+    let keys = ctx.keyring();
+
+    Ok(rows
+        .into_iter()
+        .map(|row| match row.private(&keys) {
+            Ok(private) => row.into_credentials(private).into(),
+            Err(e) => {
+                error!("decrypt failed: {e}");
+                row.into_unreadable()
+            }
+        })
+        .collect())
+}
+
+/// Record a successful login.
+pub async fn record_login(user: Uuid, slot: &Slot, db: impl PgExecutor<'_>) -> Result<()> {
+    sqlx::query!(
+        "UPDATE credentials SET (status, last_login_at) = ('ok', NOW()) WHERE uid = $1 AND slot = $2",
+        user,
+        slot.as_str()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Record a failed login, or that the credentials couldn't be decrypted.
+pub async fn record_failure(
+    user: Uuid,
+    slot: &Slot,
+    status: Status,
+    reason: &str,
+    db: impl PgExecutor<'_>,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+          UPDATE credentials SET (status, last_failure_at, failure_reason) = ($3, NOW(), $4)
+          WHERE uid = $1 AND slot = $2
+        "#,
+    )
+    .bind(user)
+    .bind(slot.as_str())
+    .bind(status)
+    .bind(reason)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Record that something failed after logging in, e.g. fetching lessons,
+/// without changing the status of the credentials.
+pub async fn record_error(
+    user: Uuid,
+    slot: &Slot,
+    reason: &str,
+    db: impl PgExecutor<'_>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+          UPDATE credentials SET (last_failure_at, failure_reason) = (NOW(), $3)
+          WHERE uid = $1 AND slot = $2
+        "#,
+        user,
+        slot.as_str(),
+        reason
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
 #[async_trait]
 impl FromRequestParts<AppState> for Credentials {
     type Rejection = AppError;
@@ -124,11 +380,78 @@ impl FromRequestParts<AppState> for Credentials {
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
         let ident = Identity::from_request_parts(parts, state).await?;
+        let slot = Slot::from_request_parts(parts, state).await?;
 
-        let credentials = get(ident.claims.sub, &state.postgres, state.aes_key())
+        let credentials = get(ident.claims.sub, &slot, state)
             .await?
             .ok_or(AppError::MissingCredentials)?;
 
         Ok(credentials)
     }
 }
+
+/// Encrypt every stored credential that isn't encrypted with the active key
+/// in the current format again, `batch_size` rows at a time. Returns the
+/// number of rows that were updated.
+///
+/// Rows that can't be decrypted are left as they are. Cached lessons and
+/// sessions are not touched, and are fetched again once their key is
+/// removed. Once every row is in the current format, `--require-aad` can be
+/// turned on. Cached lessons from before they were bound to their owner are
+/// then fetched again instead of being migrated.
+#[instrument(skip(ctx))]
+pub async fn reencrypt(batch_size: i64, ctx: &AppState) -> Result<usize> {
+    let keys = ctx.keyring();
+    let mut after: Option<(Uuid, Slot)> = None;
+    let mut updated = 0;
+
+    loop {
+        let batch = sqlx::query!(
+            r#"
+              SELECT uid, slot AS "slot: Slot", data FROM credentials
+              WHERE $1::UUID IS NULL OR (uid, slot) > ($1, $2)
+              ORDER BY uid, slot LIMIT $3
+            "#,
+            after.as_ref().map(|a| a.0),
+            after.as_ref().map(|a| a.1.as_str()),
+            batch_size
+        )
+        .fetch_all(&ctx.postgres)
+        .await?;
+
+        let last = match batch.last() {
+            Some(last) => (last.uid, last.slot.clone()),
+            None => break,
+        };
+
+        for record in batch {
+            let associated = aad(record.uid, &record.slot);
+            let data = match reencrypt_bytes(&record.data, &associated, &keys) {
+                Ok(Some(data)) => data,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!(uid = %record.uid, slot = %record.slot, error = %e, "failed to reencrypt");
+                    continue;
+                }
+            };
+
+            // skipped if the credentials were changed in the meantime
+            let res = sqlx::query!(
+                "UPDATE credentials SET data = $3 WHERE uid = $1 AND slot = $2 AND data = $4",
+                record.uid,
+                record.slot.as_str(),
+                data,
+                record.data
+            )
+            .execute(&ctx.postgres)
+            .await?;
+
+            updated += res.rows_affected() as usize;
+        }
+
+        info!(updated, "reencrypted batch");
+        after = Some(last);
+    }
+
+    Ok(updated)
+}
@@ -1,6 +1,27 @@
-use aes_gcm_siv::aead::Aead;
+//! Encryption of everything sensitive that is stored, such as credentials.
+//!
+//! Ciphertexts start with a version byte and the id of the key they were
+//! encrypted with, so that keys can be rotated. They can also be bound to
+//! associated data, such as the id of their owner, which must then be given
+//! again to decrypt them.
+//!
+//! | Version | Format                                  | Associated data  |
+//! |---------|-----------------------------------------|------------------|
+//! | 2       | `2`, key id, ciphertext, nonce          | header and `aad` |
+//! | 1       | `1`, key id, ciphertext, nonce          | none             |
+//! | none    | ciphertext, nonce (always key 0)        | none             |
+//!
+//! Older versions are still decrypted, whatever the `aad`, until they are
+//! encrypted again (see [`reencrypt`]), unless [`Keyring::require_aad`] is
+//! set. Key 0 (`AES_KEY`) is optional, and can
+//! be removed once everything has been encrypted again with another key.
+
+use std::str::FromStr;
+
+use aes_gcm_siv::aead::{Aead, Payload};
 use aes_gcm_siv::{Aes256GcmSiv, Nonce};
 use aes_gcm_siv::{Key, KeyInit};
+use hex::FromHexError;
 use rand::Rng;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
@@ -18,6 +39,12 @@ pub enum Error {
 
     #[error("ciphertext too short")]
     CiphertextTooShort,
+
+    #[error("unknown key id {0}")]
+    UnknownKey(u8),
+
+    #[error("ciphertext not bound to associated data")]
+    Unbound,
 }
 
 impl From<aes_gcm_siv::aead::Error> for Error {
@@ -28,30 +55,310 @@ impl From<aes_gcm_siv::aead::Error> for Error {
 
 const NONCE_LEN: usize = 12;
 
-pub fn encrypt_bytes(val: &impl Serialize, key: &Key<Aes256GcmSiv>) -> Result<Vec<u8>, Error> {
-    let plaintext = rmp_serde::to_vec(val)?;
-    let mut nonce = [0_u8; NONCE_LEN];
-    rand::thread_rng().fill(&mut nonce);
+const VERSION: u8 = 2;
 
-    let cipher = Aes256GcmSiv::new(key);
-    let mut out = cipher.encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())?;
-    out.extend_from_slice(&nonce);
+/// Keys other than key 0, as comma separated `<id>:<hex encoded key>` pairs.
+#[derive(Debug, Clone, Default)]
+pub struct Keys(Vec<(u8, Key<Aes256GcmSiv>)>);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseKeysError {
+    #[error("expected <id>:<key>")]
+    Format,
+
+    #[error("invalid key id")]
+    Id,
+
+    #[error("key 0 is AES_KEY")]
+    Reserved,
 
-    Ok(out)
+    #[error("invalid key: {0}")]
+    Key(#[from] FromHexError),
 }
 
-pub fn decrypt_bytes<T: DeserializeOwned>(
-    bytes: &[u8],
-    key: &Key<Aes256GcmSiv>,
-) -> Result<T, Error> {
+impl FromStr for Keys {
+    type Err = ParseKeysError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|pair| {
+                let (id, key) = pair.split_once(':').ok_or(ParseKeysError::Format)?;
+                let id = id.parse::<u8>().map_err(|_| ParseKeysError::Id)?;
+                if id == 0 {
+                    return Err(ParseKeysError::Reserved);
+                }
+
+                let mut bytes = [0; 32];
+                hex::decode_to_slice(key, &mut bytes)?;
+                Ok((id, *Key::<Aes256GcmSiv>::from_slice(&bytes)))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// The keys that can be decrypted with, and the one to encrypt with.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyring<'a> {
+    /// Key 0, unless it has been retired.
+    pub legacy: Option<&'a Key<Aes256GcmSiv>>,
+    pub others: &'a Keys,
+    pub active: u8,
+    /// Refuse older versions when decrypting with a non-empty `aad`, since
+    /// they aren't bound to it.
+    pub require_aad: bool,
+}
+
+impl<'a> Keyring<'a> {
+    pub fn get(&self, id: u8) -> Option<&'a Key<Aes256GcmSiv>> {
+        match id {
+            0 => self.legacy,
+            _ => self.others.0.iter().find(|(i, _)| *i == id).map(|(_, k)| k),
+        }
+    }
+
+    fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+        let key = self
+            .get(self.active)
+            .ok_or(Error::UnknownKey(self.active))?;
+        let mut nonce = [0_u8; NONCE_LEN];
+        rand::thread_rng().fill(&mut nonce);
+
+        let header = [VERSION, self.active];
+        let payload = Payload {
+            msg: plaintext,
+            aad: &[&header[..], aad].concat(),
+        };
+
+        let cipher = Aes256GcmSiv::new(key);
+        let mut out = header.to_vec();
+        out.extend(cipher.encrypt(Nonce::from_slice(&nonce), payload)?);
+        out.extend_from_slice(&nonce);
+
+        Ok(out)
+    }
+
+    /// Decrypt, returning the plaintext and whether it was encrypted with the
+    /// active key in the current version.
+    fn open(&self, bytes: &[u8], aad: &[u8]) -> Result<(Vec<u8>, bool), Error> {
+        let bound_only = self.require_aad && !aad.is_empty();
+
+        let versioned = match bytes {
+            [VERSION, id, rest @ ..] => self
+                .get(*id)
+                .ok_or(Error::UnknownKey(*id))
+                .and_then(|key| open_with(rest, key, &[&bytes[..2], aad].concat()))
+                .map(|plaintext| (plaintext, *id == self.active)),
+            _ if bound_only => Err(Error::Unbound),
+            [1, id, rest @ ..] => self
+                .get(*id)
+                .ok_or(Error::UnknownKey(*id))
+                .and_then(|key| open_with(rest, key, &[]))
+                .map(|plaintext| (plaintext, false)),
+            _ => {
+                return self
+                    .open_headerless(bytes)
+                    .map(|plaintext| (plaintext, false))
+            }
+        };
+
+        if bound_only {
+            return versioned;
+        }
+
+        // headerless ciphertexts can start with anything, including what
+        // looks like a header
+        versioned.or_else(|e| match self.open_headerless(bytes) {
+            Ok(plaintext) => Ok((plaintext, false)),
+            Err(_) => Err(e),
+        })
+    }
+
+    fn open_headerless(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let key = self.legacy.ok_or(Error::UnknownKey(0))?;
+        open_with(bytes, key, &[])
+    }
+}
+
+fn open_with(bytes: &[u8], key: &Key<Aes256GcmSiv>, aad: &[u8]) -> Result<Vec<u8>, Error> {
     if bytes.len() < NONCE_LEN {
         // not handling this would cause panic
         return Err(Error::CiphertextTooShort);
     }
 
-    let (ciphertext, nonce) = bytes.split_at(bytes.len() - NONCE_LEN);
+    let (msg, nonce) = bytes.split_at(bytes.len() - NONCE_LEN);
     let cipher = Aes256GcmSiv::new(key);
 
-    let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext)?;
+    Ok(cipher.decrypt(Nonce::from_slice(nonce), Payload { msg, aad })?)
+}
+
+pub fn encrypt_bytes(val: &impl Serialize, keys: &Keyring) -> Result<Vec<u8>, Error> {
+    encrypt_bytes_aad(val, &[], keys)
+}
+
+/// Encrypt, binding the ciphertext to `aad`.
+pub fn encrypt_bytes_aad(
+    val: &impl Serialize,
+    aad: &[u8],
+    keys: &Keyring,
+) -> Result<Vec<u8>, Error> {
+    // field names make the encoding robust to types with custom serializers
+    // (lessons, for example), and decoding accepts both forms
+    let plaintext = rmp_serde::to_vec_named(val)?;
+    keys.seal(&plaintext, aad)
+}
+
+pub fn decrypt_bytes<T: DeserializeOwned>(bytes: &[u8], keys: &Keyring) -> Result<T, Error> {
+    decrypt_bytes_aad(bytes, &[], keys)
+}
+
+/// Decrypt a ciphertext that was bound to `aad`.
+pub fn decrypt_bytes_aad<T: DeserializeOwned>(
+    bytes: &[u8],
+    aad: &[u8],
+    keys: &Keyring,
+) -> Result<T, Error> {
+    let (plaintext, _) = keys.open(bytes, aad)?;
     rmp_serde::from_slice(&plaintext).map_err(|e| e.into())
 }
+
+/// Encrypt `bytes` again with the active key and bind it to `aad`, unless
+/// that is already the case.
+pub fn reencrypt(bytes: &[u8], aad: &[u8], keys: &Keyring) -> Result<Option<Vec<u8>>, Error> {
+    match keys.open(bytes, aad)? {
+        (_, true) => Ok(None),
+        (plaintext, false) => keys.seal(&plaintext, aad).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm_siv::{aead::Aead, Aes256GcmSiv, Key, KeyInit, Nonce};
+
+    use super::{
+        decrypt_bytes, decrypt_bytes_aad, encrypt_bytes, encrypt_bytes_aad, reencrypt, Keyring,
+        Keys,
+    };
+
+    fn legacy_encrypt(key: &Key<Aes256GcmSiv>, header: &[u8]) -> Vec<u8> {
+        let nonce = [0; 12];
+        let mut out = header.to_vec();
+        out.extend(
+            Aes256GcmSiv::new(key)
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    &*rmp_serde::to_vec_named("secret").unwrap(),
+                )
+                .unwrap(),
+        );
+        out.extend_from_slice(&nonce);
+        out
+    }
+
+    #[test]
+    fn rotation() {
+        let legacy = Key::<Aes256GcmSiv>::from([1; 32]);
+        let others = format!("1:{}, 2:{}", hex::encode([2; 32]), hex::encode([3; 32]))
+            .parse::<Keys>()
+            .unwrap();
+        let keys = |active| Keyring {
+            legacy: Some(&legacy),
+            others: &others,
+            active,
+            require_aad: false,
+        };
+
+        // the format before key ids
+        let headerless = legacy_encrypt(&legacy, &[]);
+        assert_eq!(
+            decrypt_bytes::<String>(&headerless, &keys(2)).unwrap(),
+            "secret"
+        );
+
+        let old = encrypt_bytes(&"secret", &keys(1)).unwrap();
+        assert_eq!(&old[..2], &[2, 1]);
+
+        let new = reencrypt(&old, &[], &keys(2)).unwrap().unwrap();
+        assert_eq!(&new[..2], &[2, 2]);
+        assert!(reencrypt(&new, &[], &keys(2)).unwrap().is_none());
+        assert_eq!(decrypt_bytes::<String>(&new, &keys(2)).unwrap(), "secret");
+
+        let retired = Keys::default();
+        let without_1 = Keyring {
+            legacy: Some(&legacy),
+            others: &retired,
+            active: 0,
+            require_aad: false,
+        };
+        assert!(decrypt_bytes::<String>(&old, &without_1).is_err());
+        assert!(encrypt_bytes(&"secret", &keys(3)).is_err());
+
+        // once key 0 is retired, only headerless ciphertexts are lost
+        let without_0 = Keyring {
+            legacy: None,
+            others: &others,
+            active: 2,
+            require_aad: false,
+        };
+        assert!(decrypt_bytes::<String>(&headerless, &without_0).is_err());
+        assert_eq!(decrypt_bytes::<String>(&new, &without_0).unwrap(), "secret");
+    }
+
+    #[test]
+    fn associated_data() {
+        let legacy = Key::<Aes256GcmSiv>::from([1; 32]);
+        let others = format!("1:{}", hex::encode([2; 32]))
+            .parse::<Keys>()
+            .unwrap();
+        let keys = Keyring {
+            legacy: Some(&legacy),
+            others: &others,
+            active: 1,
+            require_aad: false,
+        };
+
+        let bound = encrypt_bytes_aad(&"secret", b"alice", &keys).unwrap();
+        assert_eq!(
+            decrypt_bytes_aad::<String>(&bound, b"alice", &keys).unwrap(),
+            "secret"
+        );
+        assert!(decrypt_bytes_aad::<String>(&bound, b"bob", &keys).is_err());
+        assert!(decrypt_bytes::<String>(&bound, &keys).is_err());
+
+        // older versions aren't bound to anything until they are reencrypted
+        let v1 = legacy_encrypt(&others.0[0].1, &[1, 1]);
+        assert_eq!(
+            decrypt_bytes_aad::<String>(&v1, b"bob", &keys).unwrap(),
+            "secret"
+        );
+        let v2 = reencrypt(&v1, b"alice", &keys).unwrap().unwrap();
+        assert!(decrypt_bytes_aad::<String>(&v2, b"bob", &keys).is_err());
+        assert!(reencrypt(&v2, b"alice", &keys).unwrap().is_none());
+
+        let strict = Keyring {
+            require_aad: true,
+            ..keys
+        };
+        let headerless = legacy_encrypt(&legacy, &[]);
+        assert!(decrypt_bytes_aad::<String>(&v1, b"alice", &strict).is_err());
+        assert!(decrypt_bytes_aad::<String>(&headerless, b"alice", &strict).is_err());
+        assert_eq!(
+            decrypt_bytes_aad::<String>(&v2, b"alice", &strict).unwrap(),
+            "secret"
+        );
+        // nothing is bound to an empty `aad` anyway
+        assert_eq!(decrypt_bytes::<String>(&v1, &strict).unwrap(), "secret");
+    }
+
+    #[test]
+    fn parse_keys() {
+        assert!("".parse::<Keys>().unwrap().0.is_empty());
+        assert!(format!("0:{}", hex::encode([0; 32]))
+            .parse::<Keys>()
+            .is_err());
+        assert!("1:abc".parse::<Keys>().is_err());
+        assert!("abc".parse::<Keys>().is_err());
+    }
+}
@@ -18,6 +18,12 @@ pub enum AppError {
     #[error("timetable not found")]
     TimetableNotFound,
 
+    #[error("bad credentials")]
+    BadCredentials,
+
+    #[error("scraping failed")]
+    ScrapingFailed,
+
     #[error("missing credentials")]
     MissingCredentials,
 
@@ -38,6 +44,8 @@ impl IntoResponse for AppError {
                     AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
                     AppError::NotFound(_) => StatusCode::NOT_FOUND,
                     AppError::TimetableNotFound => StatusCode::NOT_FOUND,
+                    AppError::BadCredentials => StatusCode::BAD_REQUEST,
+                    AppError::ScrapingFailed => StatusCode::INTERNAL_SERVER_ERROR,
                     AppError::MissingCredentials => StatusCode::UNAUTHORIZED,
                     AppError::InvalidShareLink => StatusCode::UNAUTHORIZED,
                     Self::Auth(_e) => unreachable!(),
@@ -52,11 +60,11 @@ impl IntoResponse for AppError {
 impl From<skolplattformen::Error> for AppError {
     fn from(e: skolplattformen::Error) -> Self {
         match e {
-            skolplattformen::Error::BadCredentials => Self::BadRequest("bad credentials"),
-            skolplattformen::Error::Http(_) => e.into(),
+            skolplattformen::Error::BadCredentials => Self::BadCredentials,
+            skolplattformen::Error::Http(e) => e.into(),
             skolplattformen::Error::ScrapingFailed { .. } => {
                 error!("{:?}", e);
-                Self::InternalError
+                Self::ScrapingFailed
             }
         }
     }
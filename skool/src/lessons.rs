@@ -0,0 +1,374 @@
+//! Fetching lessons, with a per-user and per-week cache in front of the
+//! upstream services.
+
+use chrono::{Datelike, Duration, IsoWeek, NaiveDate, Utc, Weekday};
+use futures::{stream, StreamExt, TryStreamExt};
+use skolplattformen::schedule::lessons_by_week;
+use skool_agenda::{diff, merge, source::Tagged, Lesson, LessonLike, Source};
+use sqlx::PgExecutor;
+use tracing::{debug, instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    credentials::Slot,
+    crypt::{decrypt_bytes_aad, encrypt_bytes_aad},
+    error::AppError,
+    push,
+    session::{self, Session},
+    util::IsoWeekExt,
+    webhooks, AppState, Result, TIMEZONE,
+};
+
+/// How long cached lessons stay fresh, depending on how far the week is from
+/// the current one.
+#[derive(Debug, Clone, clap::Args)]
+pub struct CacheConfig {
+    /// Seconds for which weeks before the current one stay fresh. They
+    /// rarely change.
+    #[clap(long, env, default_value = "604800")]
+    pub cache_ttl_past: i64,
+
+    /// Seconds for which the current and the next week stay fresh.
+    #[clap(long, env, default_value = "900")]
+    pub cache_ttl_near: i64,
+
+    /// Seconds for which weeks further ahead stay fresh.
+    #[clap(long, env, default_value = "10800")]
+    pub cache_ttl_far: i64,
+}
+
+impl CacheConfig {
+    pub fn ttl(&self, week: IsoWeek, today: NaiveDate) -> Duration {
+        let monday = |w: IsoWeek| w.with_weekday(Weekday::Mon).unwrap();
+        let distance = (monday(week) - monday(today.iso_week())).num_weeks();
+
+        Duration::seconds(match distance {
+            d if d < 0 => self.cache_ttl_past,
+            0 | 1 => self.cache_ttl_near,
+            _ => self.cache_ttl_far,
+        })
+    }
+}
+
+/// Fetch lessons straight from the upstream service, grouped by week. These
+/// are the lessons of the session's student, or of `class` (a class GUID) if
+/// given.
+pub async fn fetch(
+    session: Session,
+    class: Option<&str>,
+    weeks: impl IntoIterator<Item = IsoWeek>,
+) -> Result<Vec<(IsoWeek, Vec<Lesson>)>> {
+    match session {
+        Session::Skolplattformen(session) => {
+            let client = skolplattformen::Client::new(session)?;
+            let timetable = crate::skolplattformen::single_timetable(&client).await?;
+            let selection = match class {
+                Some(class) => skolplattformen::schedule::Selection::Class(class),
+                None => skolplattformen::schedule::Selection::Student(&timetable.person_guid),
+            };
+            let (client, unit_guid, selection) = (&client, &timetable.unit_guid, &selection);
+
+            let weeks = stream::iter(weeks)
+                .map(|week| async move {
+                    let lessons = lessons_by_week(client, unit_guid, selection, week).await?;
+                    Ok::<_, AppError>((week, lessons))
+                })
+                .buffer_unordered(8)
+                .try_collect()
+                .await?;
+
+            Ok(weeks)
+        }
+    }
+}
+
+/// Get the lessons of one of `owner`'s linked accounts, using cached weeks
+/// that are still fresh. The account is only logged in if some week has to be
+/// fetched.
+#[instrument(skip(weeks, ctx))]
+pub async fn get(
+    owner: Uuid,
+    slot: &Slot,
+    weeks: impl IntoIterator<Item = IsoWeek>,
+    ctx: &AppState,
+) -> Result<Vec<Lesson>> {
+    let weeks = weeks.into_iter().collect::<Vec<_>>();
+    let (years, numbers): (Vec<i32>, Vec<i32>) =
+        weeks.iter().map(|w| (w.year(), w.week() as i32)).unzip();
+
+    let cached = sqlx::query!(
+        r#"
+          SELECT year, week, fetched_at, data FROM schedule_cache
+          WHERE owner = $1 AND slot = $2
+            AND (year, week) IN (SELECT * FROM UNNEST($3::INTEGER[], $4::INTEGER[]))
+        "#,
+        owner,
+        slot.as_str(),
+        &years,
+        &numbers,
+    )
+    .fetch_all(&ctx.postgres)
+    .await?;
+
+    let now = Utc::now();
+    let today = now.with_timezone(&TIMEZONE).date_naive();
+    let mut lessons = Vec::new();
+    let mut stale = Vec::new();
+
+    for week in weeks {
+        let record = cached
+            .iter()
+            .find(|r| r.year == week.year() && r.week == week.week() as i32)
+            .filter(|r| now - r.fetched_at < ctx.config.cache.ttl(week, today));
+
+        let aad = aad(owner, slot, week);
+        match record.map(|r| decrypt_bytes_aad::<Vec<Lesson>>(&r.data, &aad, &ctx.keyring())) {
+            Some(Ok(cached)) => lessons.extend(cached),
+            Some(Err(e)) => {
+                warn!(error = %e, "failed to decrypt cached lessons");
+                stale.push(week);
+            }
+            None => stale.push(week),
+        }
+    }
+
+    debug!(stale = stale.len(), "cache lookup done");
+
+    if !stale.is_empty() {
+        lessons.extend(refresh(owner, slot, stale, ctx).await?);
+    }
+
+    Ok(lessons)
+}
+
+/// Get the lessons of all of `owner`'s linked accounts. With more than one
+/// account, the lessons are merged and every course is prefixed with the name
+/// of its slot. Accounts whose lessons can't be fetched are left out, unless
+/// all of them fail.
+#[instrument(skip(weeks, ctx))]
+pub async fn combined(
+    owner: Uuid,
+    weeks: impl IntoIterator<Item = IsoWeek>,
+    ctx: &AppState,
+) -> Result<Vec<Lesson>> {
+    let weeks = weeks.into_iter().collect::<Vec<_>>();
+    let slots = sqlx::query!(
+        r#"SELECT slot AS "slot: Slot" FROM credentials WHERE uid = $1 ORDER BY slot"#,
+        owner
+    )
+    .fetch_all(&ctx.postgres)
+    .await?
+    .into_iter()
+    .map(|r| r.slot)
+    .collect::<Vec<_>>();
+
+    match slots.as_slice() {
+        [] => Err(AppError::MissingCredentials),
+        [slot] => get(owner, slot, weeks, ctx).await,
+        _ => {
+            let mut sources = Vec::new();
+            let mut last_error = AppError::MissingCredentials;
+
+            for slot in &slots {
+                match get(owner, slot, weeks.iter().copied(), ctx).await {
+                    Ok(lessons) => {
+                        let source = Source {
+                            name: slot.to_string(),
+                            color: None,
+                            prefix: Some(format!("{slot}: ")),
+                        };
+                        sources.push((source, lessons));
+                    }
+                    Err(
+                        e @ (AppError::BadCredentials
+                        | AppError::ScrapingFailed
+                        | AppError::TimetableNotFound),
+                    ) => {
+                        warn!(%slot, error = %e, "skipping account in combined lessons");
+                        last_error = e;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if sources.is_empty() {
+                return Err(last_error);
+            }
+
+            Ok(merge(sources).into_iter().map(untag).collect())
+        }
+    }
+}
+
+/// Bake the source into the lesson itself.
+fn untag(tagged: Tagged<Lesson>) -> Lesson {
+    Lesson {
+        course: tagged.course().map(|c| c.into_owned()),
+        id: tagged.id(),
+        series: tagged.series(),
+        color: tagged.color().cloned(),
+        source: tagged.source().map(|s| s.into_owned()),
+        ..tagged.lesson
+    }
+}
+
+/// Fetch the lessons of one of `owner`'s linked accounts regardless of what is
+/// cached, and update the cache. Changes to weeks that were cached before are
+/// sent to the owner's webhooks and, if they are imminent, pushed to the
+/// owner's devices.
+#[instrument(skip(weeks, ctx))]
+pub async fn refresh(
+    owner: Uuid,
+    slot: &Slot,
+    weeks: impl IntoIterator<Item = IsoWeek>,
+    ctx: &AppState,
+) -> Result<Vec<Lesson>> {
+    let session = session::get(owner, slot, ctx)
+        .await?
+        .ok_or(AppError::MissingCredentials)?;
+
+    let mut lessons = Vec::new();
+
+    for (week, fetched) in fetch(session, None, weeks).await? {
+        if let Some(previous) = cached(owner, slot, week, ctx).await? {
+            let changes = diff(&previous, &fetched);
+
+            if !changes.is_empty() {
+                webhooks::enqueue(owner, slot, week, &changes, ctx).await?;
+                push::notify(owner, slot, &changes, ctx).await?;
+            }
+        }
+
+        store(owner, slot, week, &fetched, ctx).await?;
+        lessons.extend(fetched);
+    }
+
+    Ok(lessons)
+}
+
+/// Associated data of a cached week, which binds it to the account and week.
+/// Weeks cached before they were bound can't be decrypted with
+/// `--require-aad`, and are fetched again.
+fn aad(owner: Uuid, slot: &Slot, week: IsoWeek) -> Vec<u8> {
+    [
+        &b"lessons\0"[..],
+        owner.as_bytes(),
+        &week.year().to_be_bytes(),
+        &week.week().to_be_bytes(),
+        slot.as_str().as_bytes(),
+    ]
+    .concat()
+}
+
+/// The cached lessons of a week, whether fresh or not.
+async fn cached(
+    owner: Uuid,
+    slot: &Slot,
+    week: IsoWeek,
+    ctx: &AppState,
+) -> Result<Option<Vec<Lesson>>> {
+    let record = sqlx::query!(
+        "SELECT data FROM schedule_cache WHERE owner = $1 AND slot = $2 AND year = $3 AND week = $4",
+        owner,
+        slot.as_str(),
+        week.year(),
+        week.week() as i32,
+    )
+    .fetch_optional(&ctx.postgres)
+    .await?;
+
+    let aad = aad(owner, slot, week);
+    match record.map(|r| decrypt_bytes_aad(&r.data, &aad, &ctx.keyring())) {
+        Some(Ok(lessons)) => Ok(Some(lessons)),
+        Some(Err(e)) => {
+            warn!(error = %e, "failed to decrypt cached lessons");
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+async fn store(
+    owner: Uuid,
+    slot: &Slot,
+    week: IsoWeek,
+    lessons: &[Lesson],
+    ctx: &AppState,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+          INSERT INTO schedule_cache (owner, slot, year, week, data) VALUES ($1, $2, $3, $4, $5)
+          ON CONFLICT (owner, slot, year, week) DO UPDATE
+            SET (fetched_at, data) = (NOW(), EXCLUDED.data)
+        "#,
+        owner,
+        slot.as_str(),
+        week.year(),
+        week.week() as i32,
+        encrypt_bytes_aad(&lessons, &aad(owner, slot, week), &ctx.keyring())?,
+    )
+    .execute(&ctx.postgres)
+    .await?;
+
+    Ok(())
+}
+
+/// Forget all cached lessons of one of `owner`'s linked accounts, e.g.
+/// because the credentials changed.
+pub async fn purge(owner: Uuid, slot: &Slot, db: impl PgExecutor<'_>) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM schedule_cache WHERE owner = $1 AND slot = $2",
+        owner,
+        slot.as_str()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use skool_agenda::{merge, Lesson, LessonLike, Source};
+    use uuid::Uuid;
+
+    use super::untag;
+
+    #[test]
+    fn untagged_keep_source() {
+        let lesson = || Lesson {
+            teachers: Vec::new(),
+            locations: Vec::new(),
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, 8, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, 9, 0, 0).unwrap(),
+            course: Some("Fysik".to_owned()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+        let source = |name: &str| Source {
+            name: name.to_owned(),
+            color: None,
+            prefix: Some(format!("{name}: ")),
+        };
+
+        let lessons = merge([
+            (source("anna"), vec![lesson()]),
+            (source("bertil"), vec![lesson()]),
+        ])
+        .into_iter()
+        .map(untag)
+        .collect::<Vec<_>>();
+
+        let sources = lessons.iter().map(|l| l.source()).collect::<Vec<_>>();
+        assert_eq!(sources, [Some("anna".into()), Some("bertil".into())]);
+        assert_eq!(lessons[0].course.as_deref(), Some("anna: Fysik"));
+        assert_ne!(lessons[0].id, lessons[1].id);
+
+        let json = serde_json::to_value(&lessons[1]).unwrap();
+        assert_eq!(json["source"], "bertil");
+    }
+}
@@ -1,4 +1,5 @@
 use aes_gcm_siv::{Aes256GcmSiv, Key};
+use chrono_tz::Tz;
 use error::AppError;
 use hex::FromHexError;
 use sqlx::PgPool;
@@ -7,14 +8,21 @@ pub mod class;
 pub mod credentials;
 pub mod crypt;
 pub mod error;
+pub mod lessons;
+pub mod push;
 pub mod routes;
 pub mod session;
 pub mod share;
 pub mod skolplattformen;
+pub mod sync;
 mod util;
+pub mod webhooks;
 
 pub type Result<T, E = AppError> = core::result::Result<T, E>;
 
+/// Time zone used to divide lessons into days and weeks.
+pub const TIMEZONE: Tz = chrono_tz::Europe::Stockholm;
+
 #[derive(clap::Parser, Clone)]
 pub struct Config {
     #[clap(env)]
@@ -26,17 +34,52 @@ pub struct Config {
     #[clap(env)]
     pub redis_url: String,
 
+    /// Hex encoded key 0, see [`crypt`]. Can be left out once everything has
+    /// been encrypted again with another key.
     #[clap(env, parse(try_from_str = parse_hex_key))]
-    pub aes_key: Key<Aes256GcmSiv>,
+    pub aes_key: Option<Key<Aes256GcmSiv>>,
+
+    /// More keys, see [`crypt::Keys`]. Keys that are no longer active are only
+    /// used for decryption.
+    #[clap(long, env, default_value = "")]
+    pub aes_keys: crypt::Keys,
+
+    /// Id of the key that is used for encryption.
+    #[clap(long, env, default_value = "0")]
+    pub aes_key_id: u8,
+
+    /// Refuse older formats for ciphertexts that should be bound to their
+    /// owner, such as credentials and webhook secrets, see [`crypt`]. Turn on
+    /// after running `reencrypt`.
+    #[clap(long, env)]
+    pub require_aad: bool,
+
+    /// Hex encoded key for hashing share link tokens. Derived from key 0 if not
+    /// set, and required without it.
+    #[clap(long, env, parse(try_from_str = parse_hex_bytes))]
+    pub link_key: Option<[u8; 32]>,
 
     #[clap(long, env, default_value = "http://localhost:4317")]
     pub otlp_endpoint: String,
+
+    #[clap(flatten)]
+    pub cache: lessons::CacheConfig,
+
+    #[clap(flatten)]
+    pub push: push::PushConfig,
+
+    #[clap(flatten)]
+    pub webhooks: webhooks::WebhookConfig,
 }
 
 fn parse_hex_key(s: &str) -> Result<Key<Aes256GcmSiv>, FromHexError> {
+    Ok(*Key::<Aes256GcmSiv>::from_slice(&parse_hex_bytes(s)?))
+}
+
+fn parse_hex_bytes(s: &str) -> Result<[u8; 32], FromHexError> {
     let mut data = [0; 32];
     hex::decode_to_slice(s, &mut data)?;
-    Ok(*Key::<Aes256GcmSiv>::from_slice(&data))
+    Ok(data)
 }
 
 #[derive(Clone)]
@@ -47,8 +90,21 @@ pub struct AppState {
 }
 
 impl AppState {
-    pub fn aes_key(&self) -> &Key<Aes256GcmSiv> {
-        &self.config.aes_key
+    pub fn keyring(&self) -> crypt::Keyring {
+        crypt::Keyring {
+            legacy: self.config.aes_key.as_ref(),
+            others: &self.config.aes_keys,
+            active: self.config.aes_key_id,
+            require_aad: self.config.require_aad,
+        }
+    }
+
+    /// Panics without both `LINK_KEY` and key 0, which is checked at startup.
+    pub fn link_key(&self) -> [u8; 32] {
+        self.config.link_key.unwrap_or_else(|| {
+            let aes_key = self.config.aes_key.as_ref().expect("no link key");
+            blake3::derive_key("skool share link tokens", aes_key.as_slice())
+        })
     }
 }
 
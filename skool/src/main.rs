@@ -1,15 +1,15 @@
 use auth1_sdk::KeyStore;
 use axum::Extension;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
 use opentelemetry::{
     sdk::{propagation::TraceContextPropagator, trace, Resource},
     KeyValue,
 };
 use opentelemetry_otlp::WithExportConfig;
-use skool::{routes::app, AppState, Config};
+use skool::{credentials, routes::app, share, sync, webhooks, AppState, Config};
 use sqlx::postgres::PgPoolOptions;
-use tracing::metadata::LevelFilter;
+use tracing::{info, metadata::LevelFilter};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 pub fn init_telemetry(otlp_endpoint: impl Into<String>) -> anyhow::Result<()> {
@@ -49,15 +49,50 @@ pub fn init_telemetry(otlp_endpoint: impl Into<String>) -> anyhow::Result<()> {
     Ok(())
 }
 
+#[derive(Parser)]
+struct Cli {
+    #[clap(flatten)]
+    config: Config,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the API (default).
+    Serve,
+    /// Prefetch upcoming weeks for every user in the background. Webhooks are
+    /// delivered and old link accesses pruned too, unless `--once` is given.
+    Sync {
+        #[clap(flatten)]
+        config: sync::SyncConfig,
+
+        /// Run a single round and exit.
+        #[clap(long)]
+        once: bool,
+    },
+    /// Encrypt stored credentials and webhook secrets again with the active
+    /// key (`AES_KEY_ID`) and in the current format. Afterwards,
+    /// `--require-aad` can be turned on.
+    Reencrypt {
+        /// Number of rows updated at a time.
+        #[clap(long, default_value = "100")]
+        batch_size: i64,
+    },
+    /// Print the key that share link tokens are hashed with. When it is
+    /// derived from `AES_KEY`, it must be set as `LINK_KEY` before `AES_KEY`
+    /// is removed, or existing links stop working.
+    LinkKey,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
-    let config = Config::parse();
+    let Cli { config, command } = Cli::parse();
 
     init_telemetry(config.otlp_endpoint.clone())?;
 
-    let key_store = KeyStore::default();
-
     let db = PgPoolOptions::new()
         .max_connections(10)
         .connect(&config.database_url)
@@ -74,7 +109,55 @@ async fn main() -> anyhow::Result<()> {
         config,
     };
 
-    let app = app().with_state(ctx.clone()).layer(Extension(key_store));
+    anyhow::ensure!(
+        ctx.keyring().get(ctx.config.aes_key_id).is_some(),
+        "no key with id {}",
+        ctx.config.aes_key_id
+    );
+    anyhow::ensure!(
+        ctx.config.link_key.is_some() || ctx.config.aes_key.is_some(),
+        "LINK_KEY is required without AES_KEY"
+    );
+
+    match command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            let hashed = share::hash_legacy_tokens(&ctx).await?;
+            if hashed > 0 {
+                info!(hashed, "hashed legacy share link tokens");
+            }
+
+            tokio::spawn(webhooks::run(ctx.clone()));
+            tokio::spawn(share::run_pruning(ctx.clone()));
+            serve(ctx).await
+        }
+        Command::Sync { config, once: true } => Ok(sync::round(&config, &ctx).await?),
+        Command::Sync {
+            config,
+            once: false,
+        } => {
+            tokio::spawn(webhooks::run(ctx.clone()));
+            tokio::spawn(share::run_pruning(ctx.clone()));
+            sync::run(&config, &ctx).await;
+            Ok(())
+        }
+        Command::Reencrypt { batch_size } => {
+            let updated = credentials::reencrypt(batch_size, &ctx).await?;
+            info!(updated, "reencrypted credentials");
+            let updated = webhooks::reencrypt_secrets(&ctx).await?;
+            info!(updated, "reencrypted webhook secrets");
+            Ok(())
+        }
+        Command::LinkKey => {
+            println!("{}", hex::encode(ctx.link_key()));
+            Ok(())
+        }
+    }
+}
+
+async fn serve(ctx: AppState) -> anyhow::Result<()> {
+    let key_store = KeyStore::default();
+
+    let app = app().with_state(ctx).layer(Extension(key_store));
 
     axum::Server::bind(&"0.0.0.0:8000".parse().unwrap())
         .serve(app.into_make_service())
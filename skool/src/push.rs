@@ -0,0 +1,703 @@
+//! Web Push notifications. Payloads are encrypted according to RFC 8291 and
+//! the server identifies itself to push services with VAPID (RFC 8292).
+//!
+//! Endpoints are checked like webhook URLs (see [`webhooks::check_url`]), so
+//! that subscriptions can't be used to reach internal services.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Duration, Utc};
+use reqwest::{header, StatusCode, Url};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_128_GCM},
+    agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, ECDH_P256},
+    error::{KeyRejected, Unspecified},
+    hkdf,
+    rand::{SecureRandom, SystemRandom},
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::Serialize;
+use skool_agenda::{Change, Lesson, LessonLike, Locale};
+use tracing::{error, instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    credentials::Slot,
+    error::AppError,
+    webhooks::{self, UrlError},
+    AppState, Result, TIMEZONE,
+};
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct PushConfig {
+    /// Base64url encoded, uncompressed P-256 public key. Push notifications
+    /// are disabled unless both keys are set.
+    #[clap(long, env)]
+    pub vapid_public_key: Option<String>,
+
+    /// Base64url encoded P-256 private key.
+    #[clap(long, env)]
+    pub vapid_private_key: Option<String>,
+
+    /// Contact information for push services.
+    #[clap(long, env, default_value = "mailto:hej@skolorna.com")]
+    pub vapid_subject: String,
+}
+
+impl PushConfig {
+    /// `None` if push notifications aren't configured.
+    pub fn vapid(&self) -> Result<Option<Vapid>> {
+        let (public_key, private_key) = match (&self.vapid_public_key, &self.vapid_private_key) {
+            (Some(public_key), Some(private_key)) => (public_key, private_key),
+            _ => return Ok(None),
+        };
+
+        let key_pair = decode(public_key)
+            .zip(decode(private_key))
+            .ok_or(Error::InvalidKey)
+            .and_then(|(public_key, private_key)| {
+                Ok(EcdsaKeyPair::from_private_key_and_public_key(
+                    &ECDSA_P256_SHA256_FIXED_SIGNING,
+                    &private_key,
+                    &public_key,
+                )?)
+            })?;
+
+        Ok(Some(Vapid {
+            key_pair,
+            subject: self.vapid_subject.clone(),
+        }))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid vapid key")]
+    InvalidKey,
+
+    #[error("invalid subscription keys")]
+    InvalidSubscription,
+
+    #[error("invalid endpoint")]
+    InvalidEndpoint,
+
+    #[error("endpoint refused: {0}")]
+    Endpoint(#[from] UrlError),
+
+    #[error("payload too large")]
+    PayloadTooLarge,
+
+    #[error("crypto error")]
+    Crypto,
+
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+impl From<Unspecified> for Error {
+    fn from(_: Unspecified) -> Self {
+        Self::Crypto
+    }
+}
+
+impl From<KeyRejected> for Error {
+    fn from(_: KeyRejected) -> Self {
+        Self::InvalidKey
+    }
+}
+
+impl From<Error> for AppError {
+    fn from(e: Error) -> Self {
+        error!("push error: {e}");
+        Self::InternalError
+    }
+}
+
+pub(crate) fn encode(bytes: impl AsRef<[u8]>) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+pub(crate) fn decode(s: &str) -> Option<Vec<u8>> {
+    base64::decode_config(s.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()
+}
+
+/// The application server's identity.
+pub struct Vapid {
+    key_pair: EcdsaKeyPair,
+    subject: String,
+}
+
+impl Vapid {
+    /// The key that browsers need to subscribe (`applicationServerKey`).
+    pub fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+
+    /// The `Authorization` header for requests to `endpoint`.
+    fn authorization(&self, endpoint: &Url) -> Result<String, Error> {
+        let claims = serde_json::json!({
+            "aud": endpoint.origin().ascii_serialization(),
+            "exp": (Utc::now() + Duration::hours(12)).timestamp(),
+            "sub": self.subject,
+        });
+        let token = format!(
+            "{}.{}",
+            encode(r#"{"typ":"JWT","alg":"ES256"}"#),
+            encode(claims.to_string())
+        );
+        let signature = self.key_pair.sign(&SystemRandom::new(), token.as_bytes())?;
+
+        Ok(format!(
+            "vapid t={token}.{}, k={}",
+            encode(signature),
+            encode(self.public_key())
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Subscription {
+    pub id: Uuid,
+    pub endpoint: String,
+    #[serde(skip)]
+    pub p256dh: Vec<u8>,
+    #[serde(skip)]
+    pub auth: Vec<u8>,
+    /// Language of the notifications, `sv` or `en`.
+    pub locale: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Payloads are sent as a single record of this size.
+const RECORD_SIZE: u32 = 4096;
+
+/// Push services only have to accept 4096 bytes of body, which includes
+/// the 86 byte header, the padding delimiter and the authentication tag.
+pub const MAX_PAYLOAD: usize = 4096 - 86 - 1 - 16;
+
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8], out: &mut [u8]) -> Result<(), Unspecified> {
+    hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+        .extract(ikm)
+        .expand(&[info], Len(out.len()))?
+        .fill(out)
+}
+
+/// Derive the content encryption key and nonce from the shared secret.
+fn content_keys(
+    ecdh_secret: &[u8],
+    auth: &[u8],
+    salt: &[u8],
+    ua_public: &[u8],
+    as_public: &[u8],
+) -> Result<(LessSafeKey, Nonce), Unspecified> {
+    let mut ikm = [0; 32];
+    let info = [&b"WebPush: info\0"[..], ua_public, as_public].concat();
+    hkdf(auth, ecdh_secret, &info, &mut ikm)?;
+
+    let mut cek = [0; 16];
+    hkdf(salt, &ikm, b"Content-Encoding: aes128gcm\0", &mut cek)?;
+    let mut nonce = [0; 12];
+    hkdf(salt, &ikm, b"Content-Encoding: nonce\0", &mut nonce)?;
+
+    Ok((
+        LessSafeKey::new(UnboundKey::new(&AES_128_GCM, &cek)?),
+        Nonce::assume_unique_for_key(nonce),
+    ))
+}
+
+/// Encrypt `payload` for a user agent, using the `aes128gcm` content coding.
+pub fn encrypt(payload: &[u8], p256dh: &[u8], auth: &[u8]) -> Result<Vec<u8>, Error> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(Error::PayloadTooLarge);
+    }
+
+    let rng = SystemRandom::new();
+    let private_key = EphemeralPrivateKey::generate(&ECDH_P256, &rng)?;
+    let as_public = private_key.compute_public_key()?;
+
+    let mut salt = [0; 16];
+    rng.fill(&mut salt)?;
+
+    let (key, nonce) = agreement::agree_ephemeral(
+        private_key,
+        &UnparsedPublicKey::new(&ECDH_P256, p256dh),
+        Error::InvalidSubscription,
+        |secret| {
+            Ok(content_keys(
+                secret,
+                auth,
+                &salt,
+                p256dh,
+                as_public.as_ref(),
+            )?)
+        },
+    )?;
+
+    // a single record, so the padding delimiter is 2
+    let mut record = Vec::with_capacity(payload.len() + 1 + AES_128_GCM.tag_len());
+    record.extend_from_slice(payload);
+    record.push(2);
+    key.seal_in_place_append_tag(nonce, Aad::empty(), &mut record)?;
+
+    let mut body = Vec::with_capacity(16 + 4 + 1 + as_public.as_ref().len() + record.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(as_public.as_ref().len() as u8);
+    body.extend_from_slice(as_public.as_ref());
+    body.extend_from_slice(&record);
+
+    Ok(body)
+}
+
+/// Push a message to a subscription, unless its endpoint no longer resolves
+/// to public addresses. Redirects aren't followed.
+async fn attempt(
+    vapid: &Vapid,
+    subscription: &Subscription,
+    payload: &[u8],
+    ttl: u32,
+    allow_private: bool,
+) -> Result<StatusCode, Error> {
+    let (url, addrs) = webhooks::check_url(&subscription.endpoint, allow_private).await?;
+
+    send(
+        &webhooks::client(&url, &addrs),
+        vapid,
+        subscription,
+        payload,
+        ttl,
+    )
+    .await
+}
+
+/// Push a message to a subscription. `ttl` is how many seconds the push
+/// service should keep trying if the device is offline.
+pub async fn send(
+    client: &reqwest::Client,
+    vapid: &Vapid,
+    subscription: &Subscription,
+    payload: &[u8],
+    ttl: u32,
+) -> Result<StatusCode, Error> {
+    let endpoint = Url::parse(&subscription.endpoint).map_err(|_| Error::InvalidEndpoint)?;
+    let body = encrypt(payload, &subscription.p256dh, &subscription.auth)?;
+
+    let res = client
+        .post(endpoint.clone())
+        .header(header::AUTHORIZATION, vapid.authorization(&endpoint)?)
+        .header(header::CONTENT_ENCODING, "aes128gcm")
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header("ttl", ttl)
+        .header("urgency", "high")
+        .body(body)
+        .send()
+        .await?;
+
+    Ok(res.status())
+}
+
+#[derive(Debug, Serialize)]
+struct Notification {
+    title: String,
+    body: String,
+    /// The linked account whose schedule changed.
+    slot: Slot,
+}
+
+fn time(lesson: &Lesson, locale: Locale) -> String {
+    let start = lesson.start().with_timezone(&TIMEZONE);
+    format!(
+        "{} {}",
+        locale.weekday(start.weekday()),
+        start.format("%H:%M")
+    )
+}
+
+fn course(lesson: &Lesson, locale: Locale) -> String {
+    lesson.course().map_or_else(
+        || {
+            match locale {
+                Locale::Sv => "Lektion",
+                Locale::En => "Lesson",
+            }
+            .to_owned()
+        },
+        |c| c.into_owned(),
+    )
+}
+
+/// A line about the change in `locale`, if it concerns a lesson between now
+/// and `until`.
+fn describe(
+    change: &Change<Lesson>,
+    now: DateTime<Utc>,
+    until: DateTime<Utc>,
+    locale: Locale,
+) -> Option<String> {
+    let soon = |l: &Lesson| l.start() >= now && l.start() < until;
+    let lesson = |l: &Lesson| format!("{} {}", course(l, locale), time(l, locale));
+
+    let line = match *change {
+        Change::Removed { lesson: l } if soon(l) => match locale {
+            Locale::Sv => format!("{} är inställd", lesson(l)),
+            Locale::En => format!("{} is cancelled", lesson(l)),
+        },
+        Change::Added { lesson: l } if soon(l) => match locale {
+            Locale::Sv => format!("{} har lagts till", lesson(l)),
+            Locale::En => format!("{} was added", lesson(l)),
+        },
+        Change::Moved { before, after } if soon(before) || soon(after) => match locale {
+            Locale::Sv => format!(
+                "{} har flyttats till {}",
+                lesson(before),
+                time(after, locale)
+            ),
+            Locale::En => format!("{} was moved to {}", lesson(before), time(after, locale)),
+        },
+        Change::Changed { before, after } if soon(after) => match after.location() {
+            Some(location) if before.location().as_ref() != Some(&location) => match locale {
+                Locale::Sv => format!("{} är nu i {location}", lesson(after)),
+                Locale::En => format!("{} is now in {location}", lesson(after)),
+            },
+            _ => match locale {
+                Locale::Sv => format!("{} har ändrats", lesson(after)),
+                Locale::En => format!("{} has changed", lesson(after)),
+            },
+        },
+        _ => return None,
+    };
+
+    Some(line)
+}
+
+/// The notification about `changes` to the schedule of `slot` in `locale`, if
+/// any of them concern lessons between now and `until`. Other accounts than
+/// the default one are named in the title.
+fn notification(
+    changes: &[Change<Lesson>],
+    slot: &Slot,
+    now: DateTime<Utc>,
+    until: DateTime<Utc>,
+    locale: Locale,
+) -> Option<Notification> {
+    let lines = changes
+        .iter()
+        .filter_map(|c| describe(c, now, until, locale))
+        .collect::<Vec<_>>();
+
+    if lines.is_empty() {
+        return None;
+    }
+
+    let mut body = lines.join("\n");
+    if body.len() > 1000 {
+        body = match locale {
+            Locale::Sv => format!("{} ändringar i ditt schema", lines.len()),
+            Locale::En => format!("{} changes to your schedule", lines.len()),
+        };
+    }
+
+    let title = match locale {
+        Locale::Sv => "Schemat har ändrats",
+        Locale::En => "Schedule changed",
+    };
+    let title = if slot.as_str() == Slot::DEFAULT {
+        title.to_owned()
+    } else {
+        format!("{title} ({slot})")
+    };
+
+    Some(Notification {
+        title,
+        body,
+        slot: slot.clone(),
+    })
+}
+
+/// Notify `owner` about changes to lessons of one of their linked accounts
+/// before the end of tomorrow. Nothing is sent if push notifications aren't
+/// configured.
+#[instrument(skip(changes, ctx))]
+pub async fn notify(
+    owner: Uuid,
+    slot: &Slot,
+    changes: &[Change<'_, Lesson>],
+    ctx: &AppState,
+) -> Result<()> {
+    let vapid = match ctx.config.push.vapid()? {
+        Some(vapid) => vapid,
+        None => return Ok(()),
+    };
+
+    let now = Utc::now();
+    let until = (now.with_timezone(&TIMEZONE).date_naive() + Duration::days(2))
+        .and_hms_opt(0, 0, 0)
+        .and_then(|d| d.and_local_timezone(TIMEZONE).earliest())
+        .ok_or(AppError::InternalError)?
+        .with_timezone(&Utc);
+
+    // rendered per locale, and only if there is anything to say
+    let mut payloads = HashMap::new();
+    for locale in [Locale::Sv, Locale::En] {
+        if let Some(notification) = notification(changes, slot, now, until, locale) {
+            let payload = serde_json::to_vec(&notification).map_err(|e| {
+                error!(error = %e, "failed to serialize notification");
+                AppError::InternalError
+            })?;
+            payloads.insert(locale, payload);
+        }
+    }
+
+    if payloads.is_empty() {
+        return Ok(());
+    }
+
+    let subscriptions: Vec<Subscription> =
+        sqlx::query_as("SELECT * FROM push_subscriptions WHERE owner = $1")
+            .bind(owner)
+            .fetch_all(&ctx.postgres)
+            .await?;
+
+    // don't keep the caller waiting for the push services
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let allow_private = ctx.config.webhooks.webhook_allow_private;
+        let ttl = (until - now).num_seconds() as u32;
+
+        for subscription in subscriptions {
+            let locale = subscription.locale.parse().unwrap_or_default();
+            let payload = match payloads.get(&locale) {
+                Some(payload) => payload,
+                None => continue,
+            };
+
+            match attempt(&vapid, &subscription, payload, ttl, allow_private).await {
+                // the subscription has expired or been revoked
+                Ok(StatusCode::NOT_FOUND | StatusCode::GONE) => {
+                    let res = sqlx::query!(
+                        "DELETE FROM push_subscriptions WHERE id = $1",
+                        subscription.id
+                    )
+                    .execute(&ctx.postgres)
+                    .await;
+
+                    if let Err(e) = res {
+                        error!(error = %e, "failed to delete push subscription");
+                    }
+                }
+                Ok(status) if !status.is_success() => {
+                    warn!(subscription = %subscription.id, %status, "push rejected");
+                }
+                Ok(_) => {}
+                Err(e) => warn!(subscription = %subscription.id, error = %e, "push failed"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+    use ring::{
+        aead::Aad,
+        agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, ECDH_P256},
+        rand::{SecureRandom, SystemRandom},
+        signature::{self, EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+    };
+    use skool_agenda::{Change, Lesson, Locale};
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    use super::{content_keys, decode, notification, send, Subscription, Vapid};
+    use crate::{
+        credentials::Slot,
+        webhooks::{check_url, client},
+    };
+
+    /// The example from RFC 8291, section 5.
+    #[test]
+    fn rfc8291_example() {
+        let body = decode("DGv6ra1nlYgDCS1FRnbzlwAAEABBBP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A_yl95bQpu6cVPTpK4Mqgkf1CXztLVBSt2Ks3oZwbuwXPXLWyouBWLVWGNWQexSgSxsj_Qulcy4a-fN").unwrap();
+        let (key, nonce) = content_keys(
+            &decode("kyrL1jIIOHEzg3sM2ZWRHDRB62YACZhhSlknJ672kSs").unwrap(),
+            &decode("BTBZMqHH6r4Tts7J_aSIgg").unwrap(),
+            &body[..16],
+            &decode("BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4").unwrap(),
+            &body[21..86],
+        )
+        .unwrap();
+
+        let mut record = b"When I grow up, I want to be a watermelon\x02".to_vec();
+        key.seal_in_place_append_tag(nonce, Aad::empty(), &mut record)
+            .unwrap();
+        assert_eq!(record, &body[86..]);
+    }
+
+    type Received = mpsc::UnboundedSender<(HeaderMap, Bytes)>;
+
+    #[tokio::test]
+    async fn push_service_stub() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/push/:id",
+                post(
+                    |State(tx): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                        tx.send((headers, body)).unwrap();
+                        StatusCode::CREATED
+                    },
+                ),
+            )
+            .with_state(tx);
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let vapid = Vapid {
+            key_pair: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref())
+                .unwrap(),
+            subject: "mailto:test@example.com".to_owned(),
+        };
+
+        // the user agent
+        let ua_private = EphemeralPrivateKey::generate(&ECDH_P256, &rng).unwrap();
+        let ua_public = ua_private.compute_public_key().unwrap();
+        let mut auth = [0; 16];
+        rng.fill(&mut auth).unwrap();
+
+        let subscription = Subscription {
+            id: Uuid::new_v4(),
+            endpoint: format!("http://{addr}/push/abc"),
+            p256dh: ua_public.as_ref().to_vec(),
+            auth: auth.to_vec(),
+            locale: "sv".to_owned(),
+            created_at: Utc::now(),
+        };
+
+        let payload = br#"{"title":"Schedule changed"}"#;
+        let (url, addrs) = check_url(&subscription.endpoint, true).await.unwrap();
+        let status = send(&client(&url, &addrs), &vapid, &subscription, payload, 60)
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (headers, body) = rx.recv().await.unwrap();
+        assert_eq!(headers["content-encoding"], "aes128gcm");
+        assert_eq!(headers["ttl"], "60");
+
+        // check the vapid token
+        let authorization = headers["authorization"].to_str().unwrap();
+        let (token, k) = authorization
+            .strip_prefix("vapid t=")
+            .and_then(|s| s.split_once(", k="))
+            .unwrap();
+        assert_eq!(decode(k).unwrap(), vapid.public_key());
+        let (signed, signature) = token.rsplit_once('.').unwrap();
+        signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, decode(k).unwrap())
+            .verify(signed.as_bytes(), &decode(signature).unwrap())
+            .unwrap();
+        let claims: serde_json::Value =
+            serde_json::from_slice(&decode(signed.split('.').nth(1).unwrap()).unwrap()).unwrap();
+        assert_eq!(claims["aud"], format!("http://{addr}"));
+
+        // decrypt like a user agent would
+        let (salt, rest) = body.split_at(16);
+        let (record_size, rest) = rest.split_at(4);
+        assert_eq!(record_size, 4096_u32.to_be_bytes());
+        let (as_public, ciphertext) = rest[1..].split_at(usize::from(rest[0]));
+
+        let (key, nonce) = agreement::agree_ephemeral(
+            ua_private,
+            &UnparsedPublicKey::new(&ECDH_P256, as_public),
+            (),
+            |secret| {
+                content_keys(secret, &auth, salt, ua_public.as_ref(), as_public).map_err(|_| ())
+            },
+        )
+        .unwrap();
+
+        let mut record = ciphertext.to_vec();
+        let plaintext = key.open_in_place(nonce, Aad::empty(), &mut record).unwrap();
+        assert_eq!(plaintext.split_last(), Some((&2, &payload[..])));
+    }
+
+    #[test]
+    fn localized() {
+        let lesson = |hour, location: &str| Lesson {
+            teachers: Vec::new(),
+            locations: vec![location.to_owned()],
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, hour, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, hour + 1, 0, 0).unwrap(),
+            course: Some("Fysik".to_owned()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+        let (before, after, cancelled) = (lesson(8, "A1"), lesson(8, "B2"), lesson(12, "A1"));
+        let changes = [
+            Change::Changed {
+                before: &before,
+                after: &after,
+            },
+            Change::Removed { lesson: &cancelled },
+        ];
+        let now = Utc.with_ymd_and_hms(2023, 1, 9, 6, 0, 0).unwrap();
+        let until = now + Duration::days(1);
+
+        let slot = Slot::default();
+        let sv = notification(&changes, &slot, now, until, Locale::Sv).unwrap();
+        assert_eq!(sv.title, "Schemat har ändrats");
+        assert_eq!(
+            sv.body,
+            "Fysik Mån 09:00 är nu i B2\nFysik Mån 13:00 är inställd"
+        );
+
+        let en = notification(&changes, &slot, now, until, Locale::En).unwrap();
+        assert_eq!(en.title, "Schedule changed");
+        assert_eq!(
+            en.body,
+            "Fysik Mon 09:00 is now in B2\nFysik Mon 13:00 is cancelled"
+        );
+
+        assert!(notification(
+            &changes,
+            &slot,
+            until,
+            until + Duration::days(1),
+            Locale::Sv
+        )
+        .is_none());
+
+        // other accounts than the default one are named
+        let anna = Slot::try_from("anna".to_owned()).unwrap();
+        let sv = notification(&changes, &anna, now, until, Locale::Sv).unwrap();
+        assert_eq!(sv.title, "Schemat har ändrats (anna)");
+        assert_eq!(sv.slot, anna);
+        let en = notification(&changes, &anna, now, until, Locale::En).unwrap();
+        assert_eq!(en.title, "Schedule changed (anna)");
+    }
+}
@@ -3,12 +3,14 @@ use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
 
 use crate::{
     class::{self, add_to_class, Class},
+    credentials::Slot,
     session::Session,
     AppState, Result,
 };
 
 async fn list(
     ident: Identity,
+    slot: Slot,
     session: Session,
     State(ctx): State<AppState>,
 ) -> Result<impl IntoResponse> {
@@ -16,7 +18,7 @@ async fn list(
 
     let my_class = class::from_session(session).await?;
 
-    add_to_class(&my_class, ident.id(), &mut tx).await?;
+    add_to_class(&my_class, ident.id(), &slot, &mut tx).await?;
 
     let classes: Vec<Class> =
         sqlx::query_as("SELECT school, reference, name FROM classes WHERE school = $1")
@@ -1,41 +1,59 @@
 use auth1_sdk::Identity;
-use axum::{extract::State, response::IntoResponse, routing::put, Json, Router};
+use axum::{
+    extract::State,
+    response::IntoResponse,
+    routing::{get, put},
+    Json, Router,
+};
 use reqwest::StatusCode;
+use serde::Deserialize;
 use tracing::error;
 
 use crate::{
     class::{self, add_to_class},
-    credentials::{self, Credentials, PublicCredentials},
-    crypt::encrypt_bytes,
+    credentials::{self, Credentials, Health, PublicCredentials, Slot, Status},
+    crypt::encrypt_bytes_aad,
     error::AppError,
+    lessons,
     session::{self, Session},
     AppState, Result,
 };
 
+/// Routes without a `slot` parameter act on the default slot, see [`Slot`].
 async fn save_credentials(
     identity: Identity,
+    slot: Slot,
     State(ctx): State<AppState>,
     Json(creds): Json<credentials::Private>,
 ) -> Result<impl IntoResponse> {
-    let d = encrypt_bytes(&creds, ctx.aes_key())?;
+    let d = encrypt_bytes_aad(
+        &creds,
+        &credentials::aad(identity.id(), &slot),
+        &ctx.keyring(),
+    )?;
     let session = Session::create(&creds).await?;
     let mut tx = ctx.postgres.begin().await?;
 
     let record = sqlx::query!(
         r#"
-          INSERT INTO credentials (uid, data, updated_at) VALUES ($1, $2, DEFAULT)
-          ON CONFLICT (uid) DO UPDATE
-            SET (data, updated_at) = (EXCLUDED.data, EXCLUDED.updated_at)
-          RETURNING updated_at
+          INSERT INTO credentials (uid, slot, data, updated_at, status, last_login_at)
+          VALUES ($1, $2, $3, DEFAULT, 'ok', NOW())
+          ON CONFLICT (uid, slot) DO UPDATE
+            SET (data, updated_at, status, last_login_at, last_failure_at, failure_reason) =
+              (EXCLUDED.data, EXCLUDED.updated_at, EXCLUDED.status, EXCLUDED.last_login_at, NULL, NULL)
+          RETURNING updated_at, lend_to_class, last_login_at
         "#,
         identity.id(),
+        slot.as_str(),
         d,
     )
     .fetch_one(&mut tx)
     .await?;
 
+    lessons::purge(identity.id(), &slot, &mut tx).await?;
+
     let mut redis = ctx.redis.get().await?;
-    session::save_to_cache(&session, identity.id(), ctx.aes_key(), &mut redis).await?;
+    session::save_to_cache(&session, identity.id(), &slot, &ctx.keyring(), &mut redis).await?;
 
     let (school, class) = match class::from_session(session).await {
         Err(e) => {
@@ -43,7 +61,7 @@ async fn save_credentials(
             (None, None)
         }
         Ok(class) => {
-            add_to_class(&class, identity.id(), &mut tx).await?;
+            add_to_class(&class, identity.id(), &slot, &mut tx).await?;
             (Some(class.school), Some(class.reference))
         }
     };
@@ -51,15 +69,33 @@ async fn save_credentials(
     tx.commit().await?;
 
     let creds = PublicCredentials {
-        public: creds.into(),
+        slot,
+        public: Some(creds.into()),
         updated_at: record.updated_at,
         school,
         class,
+        lend_to_class: record.lend_to_class,
+        health: Health {
+            status: Status::Ok,
+            last_login_at: record.last_login_at,
+            last_failure_at: None,
+            failure_reason: None,
+        },
     };
 
     Ok((StatusCode::CREATED, Json(creds)))
 }
 
+/// All linked accounts.
+async fn list_credentials(
+    identity: Identity,
+    State(ctx): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let credentials = credentials::list(identity.claims.sub, &ctx).await?;
+
+    Ok(([("cache-control", "no-cache")], Json(credentials)))
+}
+
 async fn get_credentials(credentials: Result<Credentials>) -> Result<impl IntoResponse> {
     match credentials {
         Ok(c) => Ok((
@@ -71,27 +107,71 @@ async fn get_credentials(credentials: Result<Credentials>) -> Result<impl IntoRe
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct Settings {
+    lend_to_class: bool,
+}
+
+async fn update_settings(
+    identity: Identity,
+    slot: Slot,
+    State(ctx): State<AppState>,
+    Json(Settings { lend_to_class }): Json<Settings>,
+) -> Result<impl IntoResponse> {
+    let res = sqlx::query!(
+        "UPDATE credentials SET lend_to_class = $3 WHERE uid = $1 AND slot = $2",
+        identity.claims.sub,
+        slot.as_str(),
+        lend_to_class
+    )
+    .execute(&ctx.postgres)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::NotFound("no credentials set"));
+    }
+
+    let credentials = credentials::get(identity.claims.sub, &slot, &ctx)
+        .await?
+        .ok_or(AppError::MissingCredentials)?;
+
+    Ok(Json(PublicCredentials::from(credentials)))
+}
+
+/// Cached lessons are deleted along with the credentials.
 async fn delete_credentials(
     identity: Identity,
+    slot: Slot,
     State(ctx): State<AppState>,
 ) -> Result<impl IntoResponse> {
     sqlx::query!(
-        "DELETE FROM credentials WHERE uid = $1",
-        identity.claims.sub
+        "DELETE FROM credentials WHERE uid = $1 AND slot = $2",
+        identity.claims.sub,
+        slot.as_str()
     )
     .execute(&ctx.postgres)
     .await?;
 
-    session::purge(&mut ctx.redis.get().await?, identity.claims.sub).await?;
+    session::purge(&mut ctx.redis.get().await?, identity.claims.sub, &slot).await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub fn routes() -> Router<AppState> {
-    Router::<_>::new().route(
-        "/",
-        put(save_credentials)
-            .get(get_credentials)
-            .delete(delete_credentials),
-    )
+    Router::<_>::new()
+        .route(
+            "/",
+            put(save_credentials)
+                .get(get_credentials)
+                .patch(update_settings)
+                .delete(delete_credentials),
+        )
+        .route("/all", get(list_credentials))
+        .route(
+            "/:slot",
+            put(save_credentials)
+                .get(get_credentials)
+                .patch(update_settings)
+                .delete(delete_credentials),
+        )
 }
@@ -7,7 +7,9 @@ use crate::AppState;
 
 pub mod classes;
 pub mod credentials;
+pub mod push;
 pub mod schedule;
+pub mod webhooks;
 
 #[derive(Debug, Serialize)]
 struct Health {
@@ -31,6 +33,8 @@ pub fn app() -> Router<AppState> {
         .nest("/schedule", schedule::routes())
         .nest("/credentials", credentials::routes())
         .nest("/classes", classes::routes())
+        .nest("/webhooks", webhooks::routes())
+        .nest("/push", push::routes())
         .layer(opentelemetry_tracing_layer())
         .route("/health", get(get_health))
         .layer(CorsLayer::very_permissive())
@@ -0,0 +1,153 @@
+use auth1_sdk::Identity;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{delete, get},
+    Json, Router,
+};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use skool_agenda::Locale;
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    push::{self, Subscription},
+    webhooks::{self, UrlError},
+    AppState, Result,
+};
+
+#[derive(Debug, Serialize)]
+struct Key {
+    /// Base64url encoded, to be used as `applicationServerKey`.
+    public_key: String,
+}
+
+async fn key(State(ctx): State<AppState>) -> Result<impl IntoResponse> {
+    let vapid = ctx
+        .config
+        .push
+        .vapid()?
+        .ok_or(AppError::NotFound("push notifications are not enabled"))?;
+
+    Ok(Json(Key {
+        public_key: push::encode(vapid.public_key()),
+    }))
+}
+
+/// A `PushSubscription` as serialized by browsers, and the language of the
+/// notifications.
+#[derive(Debug, Deserialize)]
+struct NewSubscription {
+    endpoint: String,
+    keys: Keys,
+    #[serde(default)]
+    locale: Locale,
+}
+
+#[derive(Debug, Deserialize)]
+struct Keys {
+    p256dh: String,
+    auth: String,
+}
+
+async fn list(identity: Identity, State(ctx): State<AppState>) -> Result<impl IntoResponse> {
+    let subscriptions: Vec<Subscription> =
+        sqlx::query_as("SELECT * FROM push_subscriptions WHERE owner = $1 ORDER BY created_at")
+            .bind(identity.claims.sub)
+            .fetch_all(&ctx.postgres)
+            .await?;
+
+    Ok(Json(subscriptions))
+}
+
+/// Subscribing with an endpoint that the user has already registered
+/// replaces the old subscription. Endpoints of other users, and endpoints that
+/// aren't public (see [`webhooks::check_url`]), are refused.
+async fn subscribe(
+    identity: Identity,
+    State(ctx): State<AppState>,
+    Json(NewSubscription {
+        endpoint,
+        keys,
+        locale,
+    }): Json<NewSubscription>,
+) -> Result<impl IntoResponse> {
+    match Url::parse(&endpoint) {
+        Ok(u) if u.scheme() == "https" => {}
+        _ => return Err(AppError::BadRequest("invalid push endpoint")),
+    }
+    match webhooks::check_url(&endpoint, ctx.config.webhooks.webhook_allow_private).await {
+        Ok(_) => {}
+        Err(UrlError::NotPublic(_)) => {
+            return Err(AppError::BadRequest("push endpoint must be public"))
+        }
+        Err(_) => return Err(AppError::BadRequest("invalid push endpoint")),
+    }
+
+    let p256dh = push::decode(&keys.p256dh)
+        .filter(|k| k.len() == 65 && k[0] == 4)
+        .ok_or(AppError::BadRequest("invalid p256dh key"))?;
+    let auth = push::decode(&keys.auth)
+        .filter(|k| k.len() == 16)
+        .ok_or(AppError::BadRequest("invalid auth secret"))?;
+
+    let id = Uuid::new_v4();
+    let record = sqlx::query!(
+        r#"
+          INSERT INTO push_subscriptions (id, owner, endpoint, p256dh, auth, locale)
+          VALUES ($1, $2, $3, $4, $5, $6)
+          ON CONFLICT (endpoint) DO UPDATE
+            SET (id, p256dh, auth, locale, created_at) = (EXCLUDED.id, EXCLUDED.p256dh, EXCLUDED.auth, EXCLUDED.locale, NOW())
+            WHERE push_subscriptions.owner = EXCLUDED.owner
+          RETURNING created_at
+        "#,
+        id,
+        identity.claims.sub,
+        endpoint,
+        p256dh,
+        auth,
+        locale.code(),
+    )
+    .fetch_optional(&ctx.postgres)
+    .await?
+    .ok_or(AppError::BadRequest("push endpoint is registered by another user"))?;
+
+    let subscription = Subscription {
+        id,
+        endpoint,
+        p256dh,
+        auth,
+        locale: locale.code().to_owned(),
+        created_at: record.created_at,
+    };
+
+    Ok((StatusCode::CREATED, Json(subscription)))
+}
+
+async fn unsubscribe(
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    State(ctx): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let res = sqlx::query!(
+        "DELETE FROM push_subscriptions WHERE owner = $1 AND id = $2",
+        identity.claims.sub,
+        id
+    )
+    .execute(&ctx.postgres)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        Err(AppError::NotFound("subscription not found"))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::<_>::new()
+        .route("/key", get(key))
+        .route("/subscriptions", get(list).post(subscribe))
+        .route("/subscriptions/:id", delete(unsubscribe))
+}
@@ -1,5 +1,6 @@
 use std::{iter, ops::RangeBounds};
 
+use auth1_sdk::Identity;
 use axum::{
     body::Body,
     extract::{FromRequestParts, Query, State},
@@ -9,32 +10,40 @@ use axum::{
     Json, Router,
 };
 use chrono::{Datelike, Duration, IsoWeek, NaiveDate, Utc, Weekday};
-use futures::{stream, StreamExt, TryStreamExt};
-use icalendar::Calendar;
-use serde::{de, Deserialize};
+use serde::{de, Deserialize, Serialize};
 
-use skolplattformen::schedule::lessons_by_week;
-use skool_agenda::{Lesson, LessonLike};
+use skool_agenda::{
+    schedule::{CourseTotals, Day, Term},
+    svg, Lesson, Schedule,
+};
 use sqlx::postgres::types::PgRange;
 use tracing::instrument;
+use uuid::Uuid;
 
 use crate::{
     class,
+    credentials::{self, Slot},
     error::AppError,
-    session::{self, Session},
+    lessons,
+    session::Session,
     share,
-    util::{IsoWeekExt, PgRangeExt},
-    AppState, Result,
+    util::IsoWeekExt,
+    AppState, Result, TIMEZONE,
 };
 
+use self::format::Format;
+
+mod format;
 mod links;
 
-#[derive(Debug, Default, Deserialize)]
+/// The `slot` query parameter picks one of the user's linked accounts. Without
+/// it, the current user's accounts are combined, and class lookups use the
+/// default slot.
+#[derive(Debug, Deserialize)]
 enum Selection {
-    Class(String),
-    OtherUser(share::Id),
-    #[default]
-    CurrentUser,
+    Class { reference: String, slot: Slot },
+    OtherUser(share::Token),
+    CurrentUser(Option<Slot>),
 }
 
 #[derive(Debug)]
@@ -54,7 +63,8 @@ impl<'de> Deserialize<'de> for ScheduleQuery {
             year: Option<i32>,
             week: Option<u32>,
             class: Option<String>,
-            share: Option<share::Id>,
+            share: Option<share::Token>,
+            slot: Option<Slot>,
         }
 
         let Fields {
@@ -62,6 +72,7 @@ impl<'de> Deserialize<'de> for ScheduleQuery {
             week,
             class,
             share,
+            slot,
         } = Fields::deserialize(deserializer)?;
 
         let week = match (year, week) {
@@ -73,10 +84,13 @@ impl<'de> Deserialize<'de> for ScheduleQuery {
             (None, None) => Utc::now().date_naive().iso_week(),
         };
 
-        let selection = match (class, share) {
-            (Some(class), None) => Selection::Class(class),
-            (None, Some(id)) => Selection::OtherUser(id),
-            (None, None) => Selection::default(),
+        let selection = match (class, share, slot) {
+            (Some(reference), None, slot) => Selection::Class {
+                reference,
+                slot: slot.unwrap_or_default(),
+            },
+            (None, Some(id), None) => Selection::OtherUser(id),
+            (None, None, slot) => Selection::CurrentUser(slot),
             _ => return Err(de::Error::custom("contradictory selection")),
         };
 
@@ -84,49 +98,94 @@ impl<'de> Deserialize<'de> for ScheduleQuery {
     }
 }
 
-async fn get_session(
+/// Whose lessons to show.
+#[derive(Debug)]
+enum Owner {
+    /// All linked accounts of the user are combined unless `slot` is given.
+    User { uid: Uuid, slot: Option<Slot> },
+    /// Looked up on behalf of `requester`, see [`class::lessons`].
+    Class {
+        school: class::SchoolHash,
+        reference: String,
+        requester: (Uuid, Slot),
+    },
+}
+
+impl Owner {
+    async fn lessons(
+        &self,
+        weeks: impl IntoIterator<Item = IsoWeek>,
+        ctx: &AppState,
+    ) -> Result<Vec<Lesson>> {
+        match self {
+            Owner::User {
+                uid,
+                slot: Some(slot),
+            } => lessons::get(*uid, slot, weeks, ctx).await,
+            Owner::User { uid, slot: None } => lessons::combined(*uid, weeks, ctx).await,
+            Owner::Class {
+                school,
+                reference,
+                requester,
+            } => class::lessons(school, reference, requester.clone(), weeks, ctx).await,
+        }
+    }
+}
+
+/// Find whose lessons to show, and what of them may be shown. `format` is
+/// recorded when a share link is used.
+async fn get_owner(
     selection: &Selection,
+    format: &str,
     ctx: &AppState,
     parts: &mut Parts,
-) -> Result<(Session, PgRange<NaiveDate>)> {
+) -> Result<(Owner, share::Options)> {
     Ok(match selection {
-        Selection::Class(class) => {
-            let session = Session::from_request_parts(parts, ctx).await?;
-            let school = class::from_session(session).await?.school;
-            let session = session::in_class(&school, class, ctx)
+        Selection::Class { reference, slot } => {
+            let requester = Identity::from_request_parts(parts, ctx).await?.id();
+            let credentials = credentials::get(requester, slot, ctx)
                 .await?
-                .ok_or(AppError::NotFound("class not found"))?;
+                .ok_or(AppError::MissingCredentials)?;
+            let school = match credentials.school {
+                Some(school) => school,
+                None => {
+                    class::from_session(Session::create(&credentials.private).await?)
+                        .await?
+                        .school
+                }
+            };
+            let owner = Owner::Class {
+                school,
+                reference: reference.clone(),
+                requester: (requester, slot.clone()),
+            };
 
-            (session, PgRange::full())
+            (owner, share::Options::default())
+        }
+        Selection::OtherUser(link) => {
+            let (uid, options) = share::get_owner(link, format, ctx).await?;
+            let owner = Owner::User {
+                uid,
+                slot: Some(options.slot.clone()),
+            };
+
+            (owner, options)
+        }
+        Selection::CurrentUser(slot) => {
+            let uid = Identity::from_request_parts(parts, ctx).await?.id();
+            let owner = Owner::User {
+                uid,
+                slot: slot.clone(),
+            };
+
+            (owner, share::Options::default())
         }
-        Selection::OtherUser(link) => share::get_session(link, ctx).await?,
-        Selection::CurrentUser => (
-            Session::from_request_parts(parts, ctx).await?,
-            PgRange::full(),
-        ),
     })
 }
 
-async fn get_lessons(
-    session: Session,
-    weeks: impl IntoIterator<Item = IsoWeek>,
-) -> Result<Vec<Lesson>> {
-    match session {
-        Session::Skolplattformen(session) => {
-            let client = skolplattformen::Client::new(session)?;
-            let timetable = crate::skolplattformen::single_timetable(&client).await?;
-            let selection = skolplattformen::schedule::Selection::Student(&timetable.person_guid);
-
-            let mut weeks = stream::iter(weeks)
-                .map(|week| lessons_by_week(&client, &timetable.unit_guid, &selection, week))
-                .buffer_unordered(8);
-            let mut out = Vec::new();
-            while let Some(week) = weeks.try_next().await? {
-                out.extend(week);
-            }
-            Ok(out)
-        }
-    }
+fn contains_week(range: &PgRange<NaiveDate>, week: IsoWeek) -> bool {
+    range.contains(&week.with_weekday(Weekday::Mon).unwrap())
+        && range.contains(&week.with_weekday(Weekday::Sun).unwrap())
 }
 
 #[instrument(skip(ctx, req))]
@@ -136,17 +195,32 @@ async fn schedule(
     req: Request<Body>,
 ) -> Result<impl IntoResponse> {
     let (mut parts, _) = req.into_parts();
-    let (session, allowed_range) = get_session(&query.selection, &ctx, &mut parts).await?;
+    let format = Format::negotiate(&mut parts, Format::Json).await?;
+    let mut options = format::Options::from_parts(&mut parts).await?;
+    let (owner, shared) = get_owner(&query.selection, format.name(), &ctx, &mut parts).await?;
+    // a course filter would make every week without those courses a break
+    options.breaks &= !shared.filters_courses();
 
-    if !(allowed_range.contains(&query.week.with_weekday(Weekday::Mon).unwrap())
-        && allowed_range.contains(&query.week.with_weekday(Weekday::Sun).unwrap()))
-    {
+    if !contains_week(&shared.range, query.week) {
         return Err(AppError::InvalidShareLink);
     }
 
-    let lessons = get_lessons(session, iter::once(query.week)).await?;
+    let mut lessons = owner.lessons(iter::once(query.week), &ctx).await?;
+    shared.apply(&mut lessons)?;
+    shared.detail.adapt(&mut options.render);
+    let days = options.days_off(
+        &lessons,
+        query.week.with_weekday(Weekday::Mon).unwrap(),
+        query.week.with_weekday(Weekday::Sun).unwrap(),
+    );
 
-    Ok(([("cache-control", "private; max-age=3600")], Json(lessons)))
+    Ok((
+        [
+            ("cache-control", "private; max-age=3600"),
+            ("vary", "accept"),
+        ],
+        format.render(lessons, &days, &options),
+    ))
 }
 
 #[instrument(skip(ctx, req))]
@@ -156,33 +230,137 @@ async fn ical(
     req: Request<Body>,
 ) -> Result<impl IntoResponse> {
     let (mut parts, _) = req.into_parts();
+    let format = Format::negotiate(&mut parts, Format::Ical).await?;
+    let mut options = format::Options::from_parts(&mut parts).await?;
     let first = Utc::now().date_naive() - Duration::weeks(4);
-    let (session, range) = get_session(&query.selection, &ctx, &mut parts).await?;
-    let mut calendar = Calendar::new();
+    let (owner, shared) = get_owner(&query.selection, format.name(), &ctx, &mut parts).await?;
+    options.breaks &= !shared.filters_courses();
 
     let weeks = first
         .iter_weeks()
         .map(|d| d.iso_week())
-        .take_while(|w| {
-            range.contains(&w.with_weekday(Weekday::Mon).unwrap())
-                && range.contains(&w.with_weekday(Weekday::Sun).unwrap())
-        })
-        .take(28);
-    let lessons = get_lessons(session, weeks).await?;
-    calendar.extend(lessons.into_iter().map(|l| l.to_event()));
+        .take_while(|w| contains_week(&shared.range, *w))
+        .take(28)
+        .collect::<Vec<_>>();
+    let mut lessons = owner.lessons(weeks.iter().copied(), &ctx).await?;
+    shared.apply(&mut lessons)?;
+    shared.detail.adapt(&mut options.render);
+
+    let days = match (weeks.first(), weeks.last()) {
+        (Some(first), Some(last)) => options.days_off(
+            &lessons,
+            first.with_weekday(Weekday::Mon).unwrap(),
+            last.with_weekday(Weekday::Sun).unwrap(),
+        ),
+        _ => Vec::new(),
+    };
+
+    Ok((
+        [("cache-control", "no-cache"), ("vary", "accept")],
+        format.render(lessons, &days, &options),
+    ))
+}
+
+#[instrument(skip(ctx, req))]
+async fn image(
+    Query(query): Query<ScheduleQuery>,
+    State(ctx): State<AppState>,
+    req: Request<Body>,
+) -> Result<impl IntoResponse> {
+    let (mut parts, _) = req.into_parts();
+    let mut options = format::Options::from_parts(&mut parts).await?;
+    let (owner, shared) = get_owner(&query.selection, "image", &ctx, &mut parts).await?;
+
+    if !contains_week(&shared.range, query.week) {
+        return Err(AppError::InvalidShareLink);
+    }
+
+    let mut lessons = owner.lessons(iter::once(query.week), &ctx).await?;
+    shared.apply(&mut lessons)?;
+    shared.detail.adapt(&mut options.render);
 
     Ok((
         [
-            ("content-type", "text/calendar"),
-            ("cache-control", "no-cache"),
+            ("content-type", "image/svg+xml"),
+            ("cache-control", "private; max-age=3600"),
         ],
-        calendar.to_string(),
+        svg::week_svg(&lessons, query.week, &options.render, &TIMEZONE),
     ))
 }
 
+#[instrument(skip(ctx, req))]
+async fn conflicts(
+    Query(query): Query<ScheduleQuery>,
+    State(ctx): State<AppState>,
+    req: Request<Body>,
+) -> Result<impl IntoResponse> {
+    let (mut parts, _) = req.into_parts();
+    let (owner, shared) = get_owner(&query.selection, "json", &ctx, &mut parts).await?;
+
+    if !contains_week(&shared.range, query.week) {
+        return Err(AppError::InvalidShareLink);
+    }
+
+    let mut lessons = owner.lessons(iter::once(query.week), &ctx).await?;
+    shared.apply(&mut lessons)?;
+    let schedule = Schedule::new(lessons);
+
+    Ok((
+        [("cache-control", "private; max-age=3600")],
+        Json(schedule.conflicts()),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Serialize)]
+struct Stats {
+    term: Term,
+    courses: Vec<CourseTotals>,
+    /// Days of the requested week.
+    days: Vec<Day>,
+}
+
+#[instrument(skip(ctx, req))]
+async fn stats(
+    Query(query): Query<ScheduleQuery>,
+    State(ctx): State<AppState>,
+    req: Request<Body>,
+) -> Result<impl IntoResponse> {
+    let (mut parts, _) = req.into_parts();
+    let (owner, shared) = get_owner(&query.selection, "json", &ctx, &mut parts).await?;
+
+    if !contains_week(&shared.range, query.week) {
+        return Err(AppError::InvalidShareLink);
+    }
+
+    let term = Term::of(query.week.with_weekday(Weekday::Mon).unwrap());
+    let weeks = term.weeks().filter(|w| contains_week(&shared.range, *w));
+    let mut lessons = owner.lessons(weeks, &ctx).await?;
+    shared.apply(&mut lessons)?;
+    let schedule = Schedule::new(lessons);
+
+    let stats = Stats {
+        term,
+        courses: schedule.course_totals(&TIMEZONE),
+        days: schedule
+            .days(&TIMEZONE)
+            .into_iter()
+            .filter(|d| {
+                let week = d.date.iso_week();
+                (week.year(), week.week()) == (query.week.year(), query.week.week())
+            })
+            .collect(),
+    };
+
+    Ok(([("cache-control", "private; max-age=3600")], Json(stats)))
+}
+
 pub fn routes() -> Router<AppState> {
     Router::<_>::new()
         .nest("/links", links::routes())
         .route("/", get(schedule))
         .route("/ical", get(ical))
+        .route("/stats", get(stats))
+        .route("/conflicts", get(conflicts))
+        .route("/image", get(image))
 }
@@ -0,0 +1,363 @@
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::{header, request::Parts},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, NaiveDate};
+use mime::Mime;
+use serde::Deserialize;
+use skool_agenda::{
+    build_calendar, export, holiday, AlarmPolicy, DayOff, Lesson, Locale, Render, Template,
+};
+
+use crate::{error::AppError, Result, TIMEZONE};
+
+/// Output format of a schedule endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Json,
+    Ical,
+    Csv,
+    Jcal,
+    Xcal,
+}
+
+impl Format {
+    const ALL: [Format; 5] = [
+        Format::Json,
+        Format::Ical,
+        Format::Csv,
+        Format::Jcal,
+        Format::Xcal,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Ical => "ical",
+            Format::Csv => "csv",
+            Format::Jcal => "jcal",
+            Format::Xcal => "xcal",
+        }
+    }
+
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Ical => "text/calendar",
+            Format::Csv => "text/csv",
+            Format::Jcal => "application/calendar+json",
+            Format::Xcal => "application/calendar+xml",
+        }
+    }
+
+    /// How closely `range` matches: 0 for `*/*`, 1 for `type/*` and 2 for
+    /// the exact type, or `None` if it doesn't match at all.
+    fn specificity(self, range: &Mime) -> Option<u8> {
+        let (ty, _) = self.content_type().split_once('/').unwrap();
+
+        if range.type_() == mime::STAR {
+            Some(0)
+        } else if range.subtype() == mime::STAR {
+            range.type_().as_str().eq_ignore_ascii_case(ty).then_some(1)
+        } else {
+            // the essence keeps suffixes such as `+xml`, unlike `subtype()`
+            range
+                .essence_str()
+                .eq_ignore_ascii_case(self.content_type())
+                .then_some(2)
+        }
+    }
+
+    /// Whether the most specific range matching the format has `q=0`.
+    fn excluded(self, ranges: &[(Mime, f32)]) -> bool {
+        ranges
+            .iter()
+            .filter_map(|(m, q)| Some((self.specificity(m)?, *q)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(false, |(_, q)| q <= 0.0)
+    }
+
+    /// Pick a format from the `format` query parameter, or else the `Accept`
+    /// header. `default` is used when neither asks for a supported format,
+    /// unless the header rules it out with `q=0`.
+    pub async fn negotiate(parts: &mut Parts, default: Format) -> Result<Self> {
+        #[derive(Debug, Deserialize)]
+        struct FormatQuery {
+            format: Option<Format>,
+        }
+
+        let Query(query) = Query::<FormatQuery>::from_request_parts(parts, &())
+            .await
+            .map_err(|_| AppError::BadRequest("unsupported format"))?;
+
+        if let Some(format) = query.format {
+            return Ok(format);
+        }
+
+        let accept = match parts
+            .headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(accept) => accept,
+            None => return Ok(default),
+        };
+
+        let mut ranges = accept
+            .split(',')
+            .filter_map(|s| s.trim().parse::<Mime>().ok())
+            .map(|m| {
+                let q = m
+                    .get_param("q")
+                    .and_then(|q| q.as_str().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (m, q)
+            })
+            .collect::<Vec<_>>();
+
+        // stable, so ties keep the client's order
+        ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        // the default comes first, so `*/*` and `type/*` prefer it
+        let candidates = || std::iter::once(default).chain(Self::ALL);
+
+        let format = ranges
+            .iter()
+            .take_while(|(_, q)| *q > 0.0)
+            .find_map(|(m, _)| {
+                candidates().find(|f| f.specificity(m).is_some() && !f.excluded(&ranges))
+            })
+            .or_else(|| candidates().find(|f| !f.excluded(&ranges)))
+            .unwrap_or(default);
+
+        Ok(format)
+    }
+
+    /// `days` are left out of JSON and CSV.
+    pub fn render(self, lessons: Vec<Lesson>, days: &[DayOff], options: &Options) -> Response {
+        let Options { render, alarms, .. } = options;
+
+        let body = match self {
+            Format::Json => return Json(lessons).into_response(),
+            Format::Ical => {
+                let mut calendar = build_calendar(lessons.into_iter(), render, *alarms, &TIMEZONE);
+                calendar.extend(days.iter().map(|day| day.to_event(render.locale)));
+                calendar.to_string()
+            }
+            Format::Csv => export::to_csv(lessons),
+            Format::Jcal => export::to_jcal(lessons, days, render).to_string(),
+            Format::Xcal => export::to_xcal(lessons, days, render),
+        };
+
+        ([(header::CONTENT_TYPE, self.content_type())], body).into_response()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Alarm {
+    #[default]
+    None,
+    Every,
+    First,
+}
+
+/// Export options shared by the calendar formats.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub render: Render,
+    /// Only applies to iCalendar output.
+    pub alarms: AlarmPolicy,
+    /// Include public holidays as all-day events.
+    pub holidays: bool,
+    /// Include school breaks, guessed from weeks without lessons.
+    pub breaks: bool,
+}
+
+impl Options {
+    /// Read the options from the query string:
+    ///
+    /// - `locale`: `sv` (default) or `en`
+    /// - `summary` and `description`: [`Template`]s, e.g. `{course} – {room}`.
+    ///   An empty `description` leaves it out.
+    /// - `alarm`: `none` (default), `every` or `first` (first lesson of the day)
+    /// - `alarm_before`: minutes, defaults to 10
+    /// - `holidays`: `true` or `false` (default)
+    /// - `breaks`: `true` or `false` (default). Ignored for share links that
+    ///   filter courses.
+    pub async fn from_parts(parts: &mut Parts) -> Result<Self> {
+        #[derive(Debug, Deserialize)]
+        struct OptionsQuery {
+            locale: Option<Locale>,
+            summary: Option<Template>,
+            description: Option<String>,
+            #[serde(default)]
+            alarm: Alarm,
+            alarm_before: Option<u16>,
+            holidays: Option<bool>,
+            breaks: Option<bool>,
+        }
+
+        let Query(query) = Query::<OptionsQuery>::from_request_parts(parts, &())
+            .await
+            .map_err(|_| AppError::BadRequest("invalid export options"))?;
+
+        let mut options = Self::default();
+        let render = &mut options.render;
+        if let Some(locale) = query.locale {
+            render.locale = locale;
+        }
+        if let Some(summary) = query.summary {
+            render.summary = summary;
+        }
+        if let Some(description) = query.description {
+            render.description = match description.as_str() {
+                "" => None,
+                s => Some(
+                    s.parse()
+                        .map_err(|_| AppError::BadRequest("invalid description template"))?,
+                ),
+            };
+        }
+
+        let before = Duration::minutes(query.alarm_before.unwrap_or(10).into());
+        options.alarms = match query.alarm {
+            Alarm::None => AlarmPolicy::None,
+            Alarm::Every => AlarmPolicy::EveryLesson { before },
+            Alarm::First => AlarmPolicy::FirstOfDay { before },
+        };
+
+        if let Some(holidays) = query.holidays {
+            options.holidays = holidays;
+        }
+        if let Some(breaks) = query.breaks {
+            options.breaks = breaks;
+        }
+
+        Ok(options)
+    }
+
+    /// Days off from `first` through `last`, according to the options.
+    pub fn days_off(&self, lessons: &[Lesson], first: NaiveDate, last: NaiveDate) -> Vec<DayOff> {
+        let mut days = Vec::new();
+
+        if self.holidays {
+            days.extend(holiday::holidays_between(first, last));
+        }
+
+        if self.breaks {
+            days.extend(holiday::school_breaks(lessons, &TIMEZONE));
+        }
+
+        days.sort();
+        days
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, Request};
+
+    use super::Format;
+
+    async fn negotiate(uri: &str, accept: Option<&str>, default: Format) -> Option<Format> {
+        let mut req = Request::builder().uri(uri);
+        if let Some(accept) = accept {
+            req = req.header(header::ACCEPT, accept);
+        }
+        let (mut parts, _) = req.body(()).unwrap().into_parts();
+        Format::negotiate(&mut parts, default).await.ok()
+    }
+
+    #[tokio::test]
+    async fn accept() {
+        let json = Some(Format::Json);
+        let csv = Some(Format::Csv);
+
+        assert_eq!(negotiate("/", None, Format::Json).await, json);
+        assert_eq!(negotiate("/", Some("text/csv"), Format::Json).await, csv);
+        assert_eq!(negotiate("/", Some("TEXT/CSV"), Format::Json).await, csv);
+        assert_eq!(negotiate("/", Some("image/png"), Format::Json).await, json);
+        assert_eq!(
+            negotiate("/", Some("text/csv;q=0.5, application/json"), Format::Ical).await,
+            json
+        );
+        assert_eq!(
+            negotiate("/", Some("application/json;q=0, text/csv"), Format::Json).await,
+            csv
+        );
+        // ties keep the client's order
+        assert_eq!(
+            negotiate(
+                "/",
+                Some("application/calendar+xml, text/csv"),
+                Format::Json
+            )
+            .await,
+            Some(Format::Xcal)
+        );
+    }
+
+    #[tokio::test]
+    async fn wildcards() {
+        assert_eq!(
+            negotiate("/", Some("*/*"), Format::Ical).await,
+            Some(Format::Ical)
+        );
+        assert_eq!(
+            negotiate("/", Some("text/*"), Format::Json).await,
+            Some(Format::Ical)
+        );
+        // the default wins when it matches
+        assert_eq!(
+            negotiate("/", Some("text/*"), Format::Csv).await,
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            negotiate("/", Some("text/csv;q=0.1, */*"), Format::Json).await,
+            Some(Format::Json)
+        );
+    }
+
+    #[tokio::test]
+    async fn exclusions() {
+        // q=0 rules a type out, even for the default
+        assert_eq!(
+            negotiate("/", Some("*/*, text/csv;q=0"), Format::Csv).await,
+            Some(Format::Json)
+        );
+        assert_eq!(
+            negotiate("/", Some("text/*, text/csv;q=0"), Format::Csv).await,
+            Some(Format::Ical)
+        );
+        assert_eq!(
+            negotiate("/", Some("text/csv;q=0"), Format::Csv).await,
+            Some(Format::Json)
+        );
+        // the most specific range decides
+        assert_eq!(
+            negotiate("/", Some("text/*;q=0, text/csv"), Format::Json).await,
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            negotiate("/", Some("text/*;q=0, image/png"), Format::Ical).await,
+            Some(Format::Json)
+        );
+    }
+
+    #[tokio::test]
+    async fn query_override() {
+        assert_eq!(
+            negotiate("/?format=xcal", Some("text/csv"), Format::Json).await,
+            Some(Format::Xcal)
+        );
+        assert_eq!(
+            negotiate("/?format=jcal", None, Format::Ical).await,
+            Some(Format::Jcal)
+        );
+        assert_eq!(negotiate("/?format=pdf", None, Format::Json).await, None);
+    }
+}
@@ -2,15 +2,17 @@ use auth1_sdk::Identity;
 use axum::{
     extract::{Path, State},
     response::IntoResponse,
-    routing::{delete, get},
+    routing::{get, patch},
     Json, Router,
 };
+use chrono::{DateTime, NaiveDate, Utc};
 use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     error::AppError,
-    share::{self, Link},
-    AppState, Result,
+    share::{self, Access, Link, Token},
+    util, AppState, Result,
 };
 
 async fn list(identity: Identity, State(ctx): State<AppState>) -> Result<impl IntoResponse> {
@@ -22,24 +24,120 @@ async fn list(identity: Identity, State(ctx): State<AppState>) -> Result<impl In
     Ok(Json(links))
 }
 
+#[derive(Debug, Serialize)]
+struct CreatedLink {
+    #[serde(flatten)]
+    link: Link,
+    token: Token,
+}
+
 async fn create(
     identity: Identity,
     State(ctx): State<AppState>,
     Json(options): Json<share::Options>,
 ) -> Result<impl IntoResponse> {
-    let link = Link::new(options);
+    options.validate()?;
+    let token = Token::new();
+    let link = Link::new(&token, options, &ctx.link_key());
 
     sqlx::query!(
-        "INSERT INTO links (owner, id, expires_at, range) VALUES ($1, $2, $3, $4)",
+        r#"
+          INSERT INTO links (owner, id, prefix, slot, label, expires_at, detail, range, include_courses, exclude_courses, course_pattern)
+          VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        "#,
         identity.claims.sub,
         link.id.as_ref(),
+        link.prefix,
+        link.options.slot.as_str(),
+        link.options.label,
         link.options.expires_at,
-        link.options.range.as_ref()
+        link.options.detail as _,
+        link.options.range.as_ref(),
+        link.options.include_courses.as_deref(),
+        &link.options.exclude_courses,
+        link.options.course_pattern,
     )
     .execute(&ctx.postgres)
     .await?;
 
-    Ok((StatusCode::CREATED, Json(link)))
+    Ok((StatusCode::CREATED, Json(CreatedLink { link, token })))
+}
+
+/// Fields to change. `null` clears the label or the expiration.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Patch {
+    #[serde(default, deserialize_with = "util::double_option")]
+    label: Option<Option<String>>,
+    #[serde(default, deserialize_with = "util::double_option")]
+    expires_at: Option<Option<DateTime<Utc>>>,
+    range: Option<util::Range<NaiveDate>>,
+}
+
+async fn patch_link(
+    identity: Identity,
+    Path(id): Path<share::Id>,
+    State(ctx): State<AppState>,
+    Json(patch): Json<Patch>,
+) -> Result<impl IntoResponse> {
+    let mut tx = ctx.postgres.begin().await?;
+
+    let mut link: Link =
+        sqlx::query_as("SELECT * FROM links WHERE owner = $1 AND id = $2 FOR UPDATE")
+            .bind(identity.claims.sub)
+            .bind(id.as_ref())
+            .fetch_optional(&mut tx)
+            .await?
+            .ok_or(AppError::NotFound("link not found"))?;
+
+    if let Some(label) = patch.label {
+        link.options.label = label;
+    }
+    if let Some(expires_at) = patch.expires_at {
+        link.options.expires_at = expires_at;
+    }
+    if let Some(range) = patch.range {
+        link.options.range = range;
+    }
+
+    link.options.validate()?;
+
+    sqlx::query!(
+        "UPDATE links SET (label, expires_at, range) = ($2, $3, $4) WHERE id = $1",
+        id.as_ref(),
+        link.options.label,
+        link.options.expires_at,
+        link.options.range.as_ref(),
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(link))
+}
+
+/// Accesses during the last 30 days, newest first.
+async fn accesses(
+    identity: Identity,
+    Path(id): Path<share::Id>,
+    State(ctx): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let accesses: Vec<Access> = sqlx::query_as(
+        r#"
+          SELECT link_accesses.accessed_at, link_accesses.format FROM link_accesses
+          JOIN links ON links.id = link_accesses.link
+          WHERE links.owner = $1 AND links.id = $2
+          ORDER BY link_accesses.accessed_at DESC
+          LIMIT 500
+        "#,
+    )
+    .bind(identity.claims.sub)
+    .bind(id.as_ref())
+    .fetch_all(&ctx.postgres)
+    .await?;
+
+    Ok(Json(accesses))
 }
 
 async fn delete_link(
@@ -65,5 +163,6 @@ async fn delete_link(
 pub fn routes() -> Router<AppState> {
     Router::<_>::new()
         .route("/", get(list).post(create))
-        .route("/{id}", delete(delete_link))
+        .route("/:id", patch(patch_link).delete(delete_link))
+        .route("/:id/accesses", get(accesses))
 }
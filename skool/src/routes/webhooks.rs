@@ -0,0 +1,134 @@
+use auth1_sdk::Identity;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{delete, get},
+    Json, Router,
+};
+use rand::RngCore;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    webhooks::{self, Delivery, UrlError, Webhook},
+    AppState, Result,
+};
+
+#[derive(Debug, Deserialize)]
+struct NewWebhook {
+    url: String,
+}
+
+/// The secret is only ever shown when the webhook is created.
+#[derive(Debug, Serialize)]
+struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    #[serde(with = "hex::serde")]
+    secret: Vec<u8>,
+}
+
+async fn list(identity: Identity, State(ctx): State<AppState>) -> Result<impl IntoResponse> {
+    let webhooks: Vec<Webhook> = sqlx::query_as(
+        "SELECT id, url, created_at FROM webhooks WHERE owner = $1 ORDER BY created_at",
+    )
+    .bind(identity.claims.sub)
+    .fetch_all(&ctx.postgres)
+    .await?;
+
+    Ok(Json(webhooks))
+}
+
+async fn create(
+    identity: Identity,
+    State(ctx): State<AppState>,
+    Json(NewWebhook { url }): Json<NewWebhook>,
+) -> Result<impl IntoResponse> {
+    match webhooks::check_url(&url, ctx.config.webhooks.webhook_allow_private).await {
+        Ok(_) => {}
+        Err(UrlError::NotPublic(_)) => {
+            return Err(AppError::BadRequest("webhook url must be public"))
+        }
+        Err(_) => return Err(AppError::BadRequest("invalid webhook url")),
+    }
+
+    let id = Uuid::new_v4();
+    let mut secret = vec![0; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+
+    let record = sqlx::query!(
+        r#"
+          INSERT INTO webhooks (id, owner, url, secret) VALUES ($1, $2, $3, $4)
+          RETURNING created_at
+        "#,
+        id,
+        identity.claims.sub,
+        url,
+        webhooks::encrypt_secret(id, &secret, &ctx.keyring())?,
+    )
+    .fetch_one(&ctx.postgres)
+    .await?;
+
+    let webhook = CreatedWebhook {
+        webhook: Webhook {
+            id,
+            url,
+            created_at: record.created_at,
+        },
+        secret,
+    };
+
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+async fn delete_webhook(
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    State(ctx): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let res = sqlx::query!(
+        "DELETE FROM webhooks WHERE owner = $1 AND id = $2",
+        identity.claims.sub,
+        id
+    )
+    .execute(&ctx.postgres)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        Err(AppError::NotFound("webhook not found"))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+/// The most recent deliveries, newest first.
+async fn deliveries(
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    State(ctx): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let deliveries: Vec<Delivery> = sqlx::query_as(
+        r#"
+          SELECT d.id, d.created_at, d.attempts, d.next_attempt_at, d.delivered_at,
+            d.last_status, d.last_error
+          FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook
+          WHERE w.owner = $1 AND w.id = $2
+          ORDER BY d.created_at DESC LIMIT 50
+        "#,
+    )
+    .bind(identity.claims.sub)
+    .bind(id)
+    .fetch_all(&ctx.postgres)
+    .await?;
+
+    Ok(Json(deliveries))
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::<_>::new()
+        .route("/", get(list).post(create))
+        .route("/:id", delete(delete_webhook))
+        .route("/:id/deliveries", get(deliveries))
+}
@@ -1,4 +1,3 @@
-use aes_gcm_siv::{Aes256GcmSiv, Key};
 use auth1_sdk::Identity;
 use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
 use deadpool_redis::redis::{self, aio::ConnectionLike};
@@ -7,9 +6,8 @@ use tracing::{debug, instrument};
 use uuid::Uuid;
 
 use crate::{
-    class::SchoolHash,
-    credentials,
-    crypt::{decrypt_bytes, encrypt_bytes},
+    credentials::{self, Slot, Status},
+    crypt::{decrypt_bytes_aad, encrypt_bytes_aad, Keyring},
     error::AppError,
     AppState, Result,
 };
@@ -34,20 +32,26 @@ impl Session {
     }
 }
 
-pub fn cache_key(user: Uuid) -> String {
-    format!("v{}:sessions:{user}", env!("CARGO_PKG_VERSION"))
+/// Sessions are encrypted with the key as associated data, so that they can't
+/// be swapped.
+pub fn cache_key(user: Uuid, slot: &Slot) -> String {
+    format!("v{}:sessions:{user}:{slot}", env!("CARGO_PKG_VERSION"))
 }
 
 pub async fn save_to_cache<C: ConnectionLike>(
     session: &Session,
     user: Uuid,
-    aes_key: &Key<Aes256GcmSiv>,
+    slot: &Slot,
+    keys: &Keyring,
     conn: &mut C,
 ) -> Result<()> {
-    let cache_key = cache_key(user);
+    let cache_key = cache_key(user, slot);
 
     redis::pipe()
-        .set(&cache_key, encrypt_bytes(session, aes_key)?)
+        .set(
+            &cache_key,
+            encrypt_bytes_aad(session, cache_key.as_bytes(), keys)?,
+        )
         .expire(&cache_key, session.ttl())
         .query_async::<_, ()>(conn)
         .await?;
@@ -55,25 +59,31 @@ pub async fn save_to_cache<C: ConnectionLike>(
     Ok(())
 }
 
-pub async fn purge<C: redis::aio::ConnectionLike>(conn: &mut C, user: Uuid) -> Result<()> {
+pub async fn purge<C: redis::aio::ConnectionLike>(
+    conn: &mut C,
+    user: Uuid,
+    slot: &Slot,
+) -> Result<()> {
     redis::cmd("DEL")
-        .arg(cache_key(user))
+        .arg(cache_key(user, slot))
         .query_async(conn)
         .await?;
     Ok(())
 }
 
 #[instrument(name = "get_session", skip(ctx))]
-pub async fn get(owner: Uuid, ctx: &AppState) -> Result<Option<Session>> {
+pub async fn get(owner: Uuid, slot: &Slot, ctx: &AppState) -> Result<Option<Session>> {
     let mut redis = ctx.redis.get().await?;
 
-    let cache_key = cache_key(owner);
+    let cache_key = cache_key(owner, slot);
 
     let cached = redis::cmd("GET")
         .arg(&cache_key)
         .query_async::<_, Option<Vec<u8>>>(&mut redis)
         .await?
-        .and_then(|bytes| decrypt_bytes::<Session>(&bytes, ctx.aes_key()).ok());
+        .and_then(|bytes| {
+            decrypt_bytes_aad::<Session>(&bytes, cache_key.as_bytes(), &ctx.keyring()).ok()
+        });
 
     if let Some(cached) = cached {
         debug!("found cached session");
@@ -82,36 +92,35 @@ pub async fn get(owner: Uuid, ctx: &AppState) -> Result<Option<Session>> {
 
     debug!("no cached session found");
 
-    match credentials::get(owner, &ctx.postgres, ctx.aes_key()).await? {
-        Some(credentials) => {
-            let session = Session::create(&credentials.private).await?;
+    let credentials = match credentials::get(owner, slot, ctx).await? {
+        Some(credentials) => credentials,
+        None => return Ok(None),
+    };
+
+    // don't risk getting the account locked by retrying a rejected password
+    if credentials.health.status == Status::Invalid {
+        return Err(AppError::BadCredentials);
+    }
 
-            save_to_cache(&session, owner, ctx.aes_key(), &mut redis).await?;
+    match Session::create(&credentials.private).await {
+        Ok(session) => {
+            credentials::record_login(owner, slot, &ctx.postgres).await?;
+            save_to_cache(&session, owner, slot, &ctx.keyring(), &mut redis).await?;
 
             Ok(Some(session))
         }
-        None => Ok(None),
+        Err(e) => {
+            let status = match e {
+                AppError::BadCredentials => Status::Invalid,
+                _ => Status::Failing,
+            };
+            credentials::record_failure(owner, slot, status, &e.to_string(), &ctx.postgres).await?;
+
+            Err(e)
+        }
     }
 }
 
-#[instrument(skip(ctx))]
-pub async fn in_class(school: &SchoolHash, class: &str, ctx: &AppState) -> Result<Option<Session>> {
-    let user = match sqlx::query!(
-        "SELECT uid FROM credentials WHERE school = $1 AND class_reference = $2",
-        school.as_ref(),
-        class
-    )
-    .fetch_optional(&ctx.postgres)
-    .await?
-    {
-        Some(record) => record.uid,
-        None => return Ok(None),
-    };
-
-    let session = get(user, ctx).await?.ok_or(AppError::InternalError)?;
-    Ok(Some(session))
-}
-
 #[async_trait]
 impl FromRequestParts<AppState> for Session {
     type Rejection = AppError;
@@ -121,8 +130,9 @@ impl FromRequestParts<AppState> for Session {
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
         let ident = Identity::from_request_parts(parts, state).await?;
+        let slot = Slot::from_request_parts(parts, state).await?;
 
-        get(ident.id(), state)
+        get(ident.id(), &slot, state)
             .await?
             .ok_or(AppError::MissingCredentials)
     }
@@ -1,95 +1,398 @@
+use std::{fmt, time::Duration};
+
 use aes_gcm_siv::aead::OsRng;
 use chrono::{DateTime, NaiveDate, Utc};
 use rand::Rng;
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::types::PgRange;
+use skool_agenda::{Lesson, Render, Template};
+use tracing::{error, info};
+use uuid::Uuid;
 
-use crate::{
-    error::AppError,
-    session::{self, Session},
-    util, AppState, Result,
-};
+use crate::{credentials::Slot, error::AppError, util, AppState, Result};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
-#[sqlx(transparent)]
-pub struct Id(#[serde(with = "hex::serde")] [u8; 32]);
+/// The secret that gives access to a link. Only a hash of it is stored, so
+/// it is shown once, when the link is created.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Token(#[serde(with = "hex::serde")] [u8; 32]);
 
-impl Id {
+impl Token {
     #[allow(clippy::new_without_default)] // debatable
     pub fn new() -> Self {
         let mut v = [0; 32];
         OsRng.fill(&mut v);
         Self(v)
     }
+
+    pub fn id(&self, key: &[u8; 32]) -> Id {
+        Id(*blake3::keyed_hash(key, &self.0).as_bytes())
+    }
+
+    /// The beginning of the hex encoded token, to tell links apart. It is
+    /// too short to be of any use to an attacker.
+    pub fn prefix(&self) -> String {
+        hex::encode(&self.0[..4])
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Token({}…)", self.prefix())
+    }
 }
 
+/// Keyed hash of a [`Token`], which is what identifies a link in the
+/// database and the API.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct Id(#[serde(with = "hex::serde")] [u8; 32]);
+
 impl AsRef<[u8]> for Id {
     fn as_ref(&self) -> &[u8] {
         &self.0
     }
 }
 
+/// How much of the timetable a link shows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "link_detail", rename_all = "snake_case")]
+pub enum Detail {
+    #[default]
+    Full,
+    /// Courses, but no teachers, locations or groups.
+    CourseOnly,
+    /// Only when one is busy or free.
+    BusyFree,
+}
+
+impl Detail {
+    /// Remove whatever the viewer isn't allowed to see.
+    pub fn redact(self, lessons: &mut [Lesson]) {
+        for lesson in lessons {
+            match self {
+                Detail::Full => {}
+                Detail::CourseOnly => {
+                    lesson.teachers.clear();
+                    lesson.locations.clear();
+                    lesson.group = None;
+                    // derived from the teachers and group, which could be
+                    // guessed from it
+                    lesson.series = None;
+                }
+                Detail::BusyFree => {
+                    lesson.teachers.clear();
+                    lesson.locations.clear();
+                    lesson.group = None;
+                    lesson.course = None;
+                    lesson.series = None;
+                    lesson.color = None;
+                }
+            }
+        }
+    }
+
+    /// Make redacted lessons read as busy rather than untitled.
+    pub fn adapt(self, render: &mut Render) {
+        if self == Detail::BusyFree {
+            render.summary = Template::text(render.locale.busy());
+            render.description = None;
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 #[serde(default, deny_unknown_fields)]
 pub struct Options {
+    /// Which of the owner's linked accounts is shared. Can't be changed once
+    /// the link is created.
+    pub slot: Slot,
+    /// Human-readable name of the link, e.g. who it was given to.
+    pub label: Option<String>,
     /// Optional expiration of the token.
     pub expires_at: Option<DateTime<Utc>>,
-    // /// Whether to share the entire timetable or just basic availability info
-    // /// (busy or free).
-    // pub detailed: bool,
+    /// Whether to share the entire timetable or just some of it.
+    pub detail: Detail,
     /// Range of weeks to share. `None` means all weeks are shared.
     pub range: util::Range<NaiveDate>,
+    /// Only share lessons of these courses. `None` means all courses.
+    pub include_courses: Option<Vec<String>>,
+    /// Never share lessons of these courses.
+    pub exclude_courses: Vec<String>,
+    /// Only share lessons whose course matches this regular expression.
+    pub course_pattern: Option<String>,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
+            slot: Slot::default(),
+            label: None,
             expires_at: None,
+            detail: Detail::default(),
             range: util::Range::full(),
+            include_courses: None,
+            exclude_courses: Vec::new(),
+            course_pattern: None,
         }
     }
 }
 
+impl Options {
+    fn course_pattern(&self) -> Result<Option<Regex>, regex::Error> {
+        self.course_pattern
+            .as_deref()
+            .map(|p| RegexBuilder::new(p).size_limit(1 << 16).build())
+            .transpose()
+    }
+
+    /// Check the options before they are saved.
+    pub fn validate(&self) -> Result<()> {
+        if self
+            .label
+            .as_ref()
+            .map_or(false, |l| l.chars().count() > 100)
+        {
+            return Err(AppError::BadRequest("label too long"));
+        }
+
+        self.course_pattern()
+            .map_err(|_| AppError::BadRequest("invalid course pattern"))?;
+
+        Ok(())
+    }
+
+    fn shows(&self, course: Option<&str>, pattern: Option<&Regex>) -> bool {
+        let listed = |courses: &[String]| course.map_or(false, |c| courses.iter().any(|l| l == c));
+
+        self.include_courses.as_deref().map_or(true, listed)
+            && !listed(&self.exclude_courses)
+            && pattern.map_or(true, |p| course.map_or(false, |c| p.is_match(c)))
+    }
+
+    /// Whether some courses are left out.
+    pub fn filters_courses(&self) -> bool {
+        self.include_courses.is_some()
+            || !self.exclude_courses.is_empty()
+            || self.course_pattern.is_some()
+    }
+
+    /// Remove the lessons that aren't shared, and redact the rest.
+    pub fn apply(&self, lessons: &mut Vec<Lesson>) -> Result<()> {
+        let pattern = self.course_pattern().map_err(|e| {
+            error!(error = %e, "invalid course pattern");
+            AppError::InternalError
+        })?;
+
+        lessons.retain(|l| self.shows(l.course.as_deref(), pattern.as_ref()));
+        self.detail.redact(lessons);
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Link {
     pub id: Id,
+    /// See [`Token::prefix`].
+    pub prefix: String,
     #[sqlx(flatten)]
     pub options: Options,
     pub last_used: Option<DateTime<Utc>>,
+    pub access_count: i64,
 }
 
 impl Link {
-    pub fn new(options: Options) -> Self {
+    pub fn new(token: &Token, options: Options, key: &[u8; 32]) -> Self {
         Self {
-            id: Id::new(),
+            id: token.id(key),
+            prefix: token.prefix(),
             options,
             last_used: None,
+            access_count: 0,
         }
     }
 }
 
-pub async fn get_session(id: &Id, ctx: &AppState) -> Result<(Session, PgRange<NaiveDate>)> {
-    let record = sqlx::query!(
-        "SELECT owner, expires_at, range FROM links WHERE id = $1",
-        &id.0
+/// An entry in the access history of a link, which is kept for 30 days.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Access {
+    pub accessed_at: DateTime<Utc>,
+    /// Format of the response, such as `json` or `ical`.
+    pub format: String,
+}
+
+/// Get the owner of a link and what they share, including which of their
+/// accounts. The access is recorded with
+/// the `format` of the response.
+pub async fn get_owner(token: &Token, format: &str, ctx: &AppState) -> Result<(Uuid, Options)> {
+    #[derive(sqlx::FromRow)]
+    struct Shared {
+        owner: Uuid,
+        #[sqlx(flatten)]
+        options: Options,
+    }
+
+    let id = token.id(&ctx.link_key());
+    let Shared { owner, options } = sqlx::query_as(
+        r#"
+          SELECT * FROM links
+          WHERE id = $1
+            AND EXISTS (SELECT FROM credentials WHERE uid = links.owner AND slot = links.slot)
+        "#,
     )
+    .bind(id.as_ref())
     .fetch_optional(&ctx.postgres)
     .await?
     .ok_or(AppError::InvalidShareLink)?;
 
-    if let Some(expires_at) = record.expires_at {
+    if let Some(expires_at) = options.expires_at {
         if Utc::now() >= expires_at {
             return Err(AppError::InvalidShareLink);
         }
     }
 
-    let session = session::get(record.owner, ctx)
-        .await?
-        .ok_or(AppError::InvalidShareLink)?;
+    sqlx::query!(
+        "UPDATE links SET last_used = NOW(), access_count = access_count + 1 WHERE id = $1",
+        &id.0
+    )
+    .execute(&ctx.postgres)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO link_accesses (link, format) VALUES ($1, $2)",
+        &id.0,
+        format
+    )
+    .execute(&ctx.postgres)
+    .await?;
+
+    Ok((owner, options))
+}
+
+/// Delete access history older than 30 days. Returns the number of accesses
+/// that were deleted.
+pub async fn prune_accesses(ctx: &AppState) -> Result<u64> {
+    let res =
+        sqlx::query!("DELETE FROM link_accesses WHERE accessed_at < NOW() - INTERVAL '30 days'")
+            .execute(&ctx.postgres)
+            .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Prune the access history every hour, forever.
+pub async fn run_pruning(ctx: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
 
-    sqlx::query!("UPDATE links SET last_used = NOW() WHERE id = $1", &id.0)
-        .execute(&ctx.postgres)
+        match prune_accesses(&ctx).await {
+            Ok(0) => {}
+            Ok(pruned) => info!(pruned, "pruned link access history"),
+            Err(e) => error!(error = %e, "failed to prune link access history"),
+        }
+    }
+}
+
+/// Hash the tokens of links that were created when tokens were stored in
+/// plaintext. Returns the number of links that were updated.
+pub async fn hash_legacy_tokens(ctx: &AppState) -> Result<usize> {
+    let key = ctx.link_key();
+    let mut tx = ctx.postgres.begin().await?;
+
+    let legacy = sqlx::query!("SELECT id FROM links WHERE prefix IS NULL FOR UPDATE")
+        .fetch_all(&mut tx)
+        .await?;
+
+    for record in &legacy {
+        let token = match <[u8; 32]>::try_from(record.id.as_slice()) {
+            Ok(token) => Token(token),
+            Err(_) => {
+                error!("malformed share link token");
+                continue;
+            }
+        };
+
+        sqlx::query!(
+            "UPDATE links SET (id, prefix) = ($2, $3) WHERE id = $1",
+            &record.id,
+            token.id(&key).as_ref(),
+            token.prefix(),
+        )
+        .execute(&mut tx)
         .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(legacy.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use skool_agenda::Lesson;
+    use uuid::Uuid;
 
-    Ok((session, record.range))
+    use super::{Detail, Options};
+
+    #[test]
+    fn redaction() {
+        let lesson = |teacher: &str, location: &str, group: &str, series| Lesson {
+            teachers: vec![teacher.to_owned()],
+            locations: vec![location.to_owned()],
+            group: Some(group.to_owned()),
+            start: Utc.with_ymd_and_hms(2023, 1, 9, 8, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, 9, 0, 0).unwrap(),
+            course: Some("Fysik".to_owned()),
+            id: Uuid::nil(),
+            series: Some(Uuid::from_u128(series)),
+            color: None,
+            source: None,
+        };
+
+        // lessons that only differ in what is redacted can't be told apart
+        for detail in [Detail::CourseOnly, Detail::BusyFree] {
+            let mut a = [lesson("ABC", "A1", "Grupp 1", 1)];
+            let mut b = [lesson("DEF", "B2", "Grupp 2", 2)];
+            detail.redact(&mut a);
+            detail.redact(&mut b);
+            assert_eq!(a, b);
+            assert_eq!(a[0].series, None);
+        }
+
+        let mut full = [lesson("ABC", "A1", "Grupp 1", 1)];
+        Detail::Full.redact(&mut full);
+        assert_eq!(full, [lesson("ABC", "A1", "Grupp 1", 1)]);
+    }
+
+    #[test]
+    fn course_filters() {
+        let options = Options {
+            include_courses: Some(vec!["Musik".to_owned(), "Bild".to_owned()]),
+            exclude_courses: vec!["Bild".to_owned()],
+            ..Default::default()
+        };
+        assert!(options.shows(Some("Musik"), None));
+        assert!(!options.shows(Some("Bild"), None));
+        assert!(!options.shows(Some("Matematik"), None));
+        assert!(!options.shows(None, None));
+
+        let options = Options {
+            course_pattern: Some("^Moderna språk".to_owned()),
+            ..Default::default()
+        };
+        let pattern = options.course_pattern().unwrap();
+        assert!(options.shows(Some("Moderna språk 3"), pattern.as_ref()));
+        assert!(!options.shows(Some("Svenska 1"), pattern.as_ref()));
+        assert!(Options::default().shows(None, None));
+
+        let options = Options {
+            course_pattern: Some("(".to_owned()),
+            ..Default::default()
+        };
+        assert!(options.validate().is_err());
+    }
 }
@@ -0,0 +1,113 @@
+//! Background job that keeps the lesson cache warm, so that requests rarely
+//! have to wait for a login.
+
+use std::time::Duration;
+
+use chrono::{Datelike, IsoWeek, Utc};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    credentials::{self, Slot},
+    error::AppError,
+    lessons, AppState, Result, TIMEZONE,
+};
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct SyncConfig {
+    /// Seconds between sync rounds.
+    #[clap(long, env, default_value = "600")]
+    pub sync_interval: u64,
+
+    /// Number of weeks to prefetch, starting with the current one.
+    #[clap(long, env, default_value = "4")]
+    pub sync_weeks: usize,
+
+    /// Number of users synced at the same time.
+    #[clap(long, env, default_value = "4")]
+    pub sync_concurrency: usize,
+}
+
+/// Sync forever. Failed rounds are logged and retried on the next tick.
+pub async fn run(config: &SyncConfig, ctx: &AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.sync_interval));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = round(config, ctx).await {
+            error!(error = %e, "sync round failed");
+        }
+    }
+}
+
+/// Sync every user once.
+#[instrument(skip_all)]
+pub async fn round(config: &SyncConfig, ctx: &AppState) -> Result<()> {
+    let today = Utc::now().with_timezone(&TIMEZONE).date_naive();
+    let weeks = today
+        .iter_weeks()
+        .map(|d| d.iso_week())
+        .take(config.sync_weeks)
+        .collect::<Vec<_>>();
+
+    let accounts = sqlx::query!(r#"SELECT uid, slot AS "slot: Slot" FROM credentials"#)
+        .fetch(&ctx.postgres)
+        .map_ok(|r| (r.uid, r.slot))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    info!(accounts = accounts.len(), "starting sync");
+
+    let failed = stream::iter(accounts)
+        .map(|(owner, slot)| account(owner, slot, &weeks, ctx))
+        .buffer_unordered(config.sync_concurrency)
+        .filter(|ok| future::ready(!ok))
+        .count()
+        .await;
+
+    info!(failed, "sync done");
+
+    Ok(())
+}
+
+/// Sync one linked account and record the outcome. Returns whether it went
+/// well.
+#[instrument(skip(weeks, ctx))]
+async fn account(owner: Uuid, slot: Slot, weeks: &[IsoWeek], ctx: &AppState) -> bool {
+    let res = lessons::refresh(owner, &slot, weeks.iter().copied(), ctx).await;
+
+    let recorded = async {
+        match &res {
+            Ok(_) => {
+                sqlx::query!(
+                    r#"
+                      INSERT INTO sync_status (owner, slot, succeeded_at) VALUES ($1, $2, NOW())
+                      ON CONFLICT (owner, slot) DO UPDATE
+                        SET succeeded_at = EXCLUDED.succeeded_at
+                    "#,
+                    owner,
+                    slot.as_str()
+                )
+                .execute(&ctx.postgres)
+                .await?;
+            }
+            // failed logins are already recorded with the credentials
+            Err(e @ (AppError::ScrapingFailed | AppError::TimetableNotFound)) => {
+                warn!(error = %e, "sync failed");
+                credentials::record_error(owner, &slot, &e.to_string(), &ctx.postgres).await?;
+            }
+            Err(e) => warn!(error = %e, "sync failed"),
+        }
+
+        Ok::<_, AppError>(())
+    }
+    .await;
+
+    if let Err(e) = recorded {
+        error!(error = %e, "failed to record sync status");
+    }
+
+    res.is_ok()
+}
@@ -1,6 +1,5 @@
-use std::ops::Bound;
-
 use chrono::{NaiveDate, Weekday};
+use serde::{Deserialize, Deserializer};
 
 mod range {
     use std::ops::{Bound, Deref};
@@ -74,7 +73,6 @@ mod range {
 }
 
 pub use range::Range;
-use sqlx::postgres::types::PgRange;
 
 pub trait IsoWeekExt: Sized {
     fn with_weekday(self, weekday: Weekday) -> Option<NaiveDate>;
@@ -86,15 +84,12 @@ impl IsoWeekExt for chrono::IsoWeek {
     }
 }
 
-pub trait PgRangeExt<T> {
-    fn full() -> Self;
-}
-
-impl<T> PgRangeExt<T> for PgRange<T> {
-    fn full() -> Self {
-        Self {
-            start: Bound::Unbounded,
-            end: Bound::Unbounded,
-        }
-    }
+/// Tell a missing field (`None`) from an explicit `null` (`Some(None)`).
+/// Use together with `#[serde(default)]`.
+pub fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
@@ -0,0 +1,603 @@
+//! Notifying users' own services when their schedule changes.
+//!
+//! Changes are found when a week is fetched again (see
+//! [`crate::lessons::refresh`]) and queued as one delivery per webhook.
+//! Deliveries are retried with exponential backoff and kept as a log.
+//!
+//! Webhooks are only sent to public addresses, so that they can't be used to
+//! probe internal services. Secrets are encrypted like credentials, see
+//! [`crate::crypt`].
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration as StdDuration,
+};
+
+use chrono::{DateTime, Datelike, Duration, IsoWeek, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{header, redirect, StatusCode, Url};
+use serde::Serialize;
+use sha2::Sha256;
+use skool_agenda::{Change, Lesson};
+use tracing::{error, instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    credentials::Slot,
+    crypt::{decrypt_bytes_aad, encrypt_bytes_aad, reencrypt as reencrypt_bytes, Keyring},
+    error::AppError,
+    AppState, Result,
+};
+
+/// `t=<unix time>,v1=<hex encoded HMAC-SHA256 of "<t>.<body>">`, see [`sign`].
+pub const SIGNATURE_HEADER: &str = "skool-signature";
+
+/// Id of the delivery, the same for every attempt.
+pub const DELIVERY_HEADER: &str = "skool-delivery";
+
+/// Deliveries are given up on after this many attempts.
+const MAX_ATTEMPTS: i32 = 8;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct WebhookConfig {
+    /// Allow webhooks and push endpoints at loopback, private and link-local
+    /// addresses, which are refused otherwise. Only meant for development and
+    /// tests.
+    #[clap(long, env)]
+    pub webhook_allow_private: bool,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Delivery {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub attempts: i32,
+    /// `None` once delivered or given up on.
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// HTTP status of the last attempt, if there was a response.
+    pub last_status: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    id: Uuid,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    created_at: DateTime<Utc>,
+    /// The linked account whose schedule changed.
+    slot: &'a Slot,
+    year: i32,
+    week: u32,
+    changes: &'a [Change<'a, Lesson>],
+}
+
+/// Sign a request body. Receivers should recompute the signature with their
+/// secret and the timestamp from the header, and reject old timestamps.
+pub fn sign(secret: &[u8], timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!(
+        "t={timestamp},v1={}",
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Associated data of an encrypted webhook secret.
+pub fn aad(id: Uuid) -> Vec<u8> {
+    [&b"webhook\0"[..], id.as_bytes()].concat()
+}
+
+/// Encrypt the secret of a webhook, bound to its id.
+pub fn encrypt_secret(id: Uuid, secret: &[u8], keys: &Keyring) -> Result<Vec<u8>> {
+    Ok(encrypt_bytes_aad(&secret, &aad(id), keys)?)
+}
+
+/// Whether webhooks may be sent to `ip`. Loopback, private, link-local and
+/// other special purpose addresses are refused, also when they are embedded
+/// in IPv6 addresses.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // shared address space, 100.64.0.0/10
+                || (a == 100 && b & 0xc0 == 64)
+                // protocol assignments, 192.0.0.0/24
+                || (a == 192 && b == 0 && c == 0)
+                // benchmarking, 198.18.0.0/15
+                || (a == 198 && b & 0xfe == 18)
+                // reserved, 240.0.0.0/4
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let embedded = |hi: u16, lo: u16| {
+                let [a, b] = hi.to_be_bytes();
+                let [c, d] = lo.to_be_bytes();
+                is_public(Ipv4Addr::new(a, b, c, d).into())
+            };
+
+            match segments {
+                // IPv4-mapped and IPv4-compatible, which includes ::1 and ::
+                [0, 0, 0, 0, 0, 0xffff | 0, hi, lo] => embedded(hi, lo),
+                // NAT64, 64:ff9b::/96
+                [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => embedded(hi, lo),
+                // local-use NAT64, 64:ff9b:1::/48
+                [0x64, 0xff9b, 1, ..] => false,
+                // 6to4, 2002::/16
+                [0x2002, hi, lo, ..] => embedded(hi, lo),
+                [first, ..] => {
+                    !(ip.is_multicast()
+                        // unique local, fc00::/7
+                        || first & 0xfe00 == 0xfc00
+                        // link-local, fe80::/10
+                        || first & 0xffc0 == 0xfe80)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UrlError {
+    #[error("invalid webhook url")]
+    Invalid,
+
+    #[error("failed to resolve host: {0}")]
+    Resolve(#[from] io::Error),
+
+    #[error("{0} is not a public address")]
+    NotPublic(IpAddr),
+}
+
+/// Check that `url` is an http(s) URL, and that its host only resolves to
+/// public addresses unless `allow_private`. The addresses are returned, and
+/// requests should only be sent to them (see [`client`]) so that the host
+/// can't resolve to something else in the meantime.
+pub async fn check_url(url: &str, allow_private: bool) -> Result<(Url, Vec<SocketAddr>), UrlError> {
+    let url = Url::parse(url).map_err(|_| UrlError::Invalid)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(UrlError::Invalid);
+    }
+
+    let host = url
+        .host_str()
+        .ok_or(UrlError::Invalid)?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = url.port_or_known_default().ok_or(UrlError::Invalid)?;
+    let addrs = tokio::net::lookup_host((host, port))
+        .await?
+        .collect::<Vec<_>>();
+
+    if addrs.is_empty() {
+        return Err(UrlError::Invalid);
+    }
+
+    if !allow_private {
+        if let Some(addr) = addrs.iter().find(|a| !is_public(a.ip())) {
+            return Err(UrlError::NotPublic(addr.ip()));
+        }
+    }
+
+    Ok((url, addrs))
+}
+
+/// Queue a `schedule.changed` event about one of `owner`'s linked accounts
+/// for every webhook of `owner`.
+#[instrument(skip(changes, ctx))]
+pub async fn enqueue(
+    owner: Uuid,
+    slot: &Slot,
+    week: IsoWeek,
+    changes: &[Change<'_, Lesson>],
+    ctx: &AppState,
+) -> Result<()> {
+    let event = Event {
+        id: Uuid::new_v4(),
+        kind: "schedule.changed",
+        created_at: Utc::now(),
+        slot,
+        year: week.year(),
+        week: week.week(),
+        changes,
+    };
+    let payload = serde_json::to_string(&event).map_err(|e| {
+        error!(error = %e, "failed to serialize event");
+        AppError::InternalError
+    })?;
+
+    sqlx::query!(
+        r#"
+          INSERT INTO webhook_deliveries (id, webhook, payload)
+          SELECT gen_random_uuid(), id, $2 FROM webhooks WHERE owner = $1
+        "#,
+        owner,
+        payload
+    )
+    .execute(&ctx.postgres)
+    .await?;
+
+    Ok(())
+}
+
+/// A client that only connects to `addrs` for the host of `url`, see
+/// [`check_url`].
+pub fn client(url: &Url, addrs: &[SocketAddr]) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .timeout(StdDuration::from_secs(10))
+        .redirect(redirect::Policy::none())
+        .user_agent(concat!("skool/", env!("CARGO_PKG_VERSION")));
+
+    if let Some(domain) = url.domain() {
+        builder = builder.resolve_to_addrs(domain, addrs);
+    }
+
+    builder.build().expect("failed to build http client")
+}
+
+/// Make a single delivery attempt.
+pub async fn send(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &[u8],
+    delivery: Uuid,
+    body: String,
+) -> Result<StatusCode, reqwest::Error> {
+    let signature = sign(secret, Utc::now().timestamp(), body.as_bytes());
+
+    let res = client
+        .post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .header(DELIVERY_HEADER, delivery.to_string())
+        .body(body)
+        .send()
+        .await?;
+
+    Ok(res.status())
+}
+
+/// Wait 30 seconds after the first attempt, then double it each time.
+fn backoff(attempts: i32) -> Duration {
+    Duration::seconds(30 << (attempts - 1).clamp(0, 16))
+}
+
+/// Make a delivery attempt, unless the URL no longer resolves to public
+/// addresses.
+async fn attempt(
+    url: &str,
+    secret: &[u8],
+    delivery: Uuid,
+    body: String,
+    allow_private: bool,
+) -> Result<StatusCode, String> {
+    let (url, addrs) = check_url(url, allow_private)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    send(&client(&url, &addrs), url.as_str(), secret, delivery, body)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Attempt every delivery that is due, once.
+#[instrument(skip_all)]
+pub async fn deliver_due(ctx: &AppState) -> Result<()> {
+    // claim the deliveries for a while, so that other workers skip them
+    let due = sqlx::query!(
+        r#"
+          UPDATE webhook_deliveries SET next_attempt_at = NOW() + INTERVAL '5 minutes'
+          FROM webhooks
+          WHERE webhooks.id = webhook_deliveries.webhook AND webhook_deliveries.id IN (
+            SELECT id FROM webhook_deliveries WHERE next_attempt_at <= NOW()
+            ORDER BY next_attempt_at LIMIT 50
+            FOR UPDATE SKIP LOCKED
+          )
+          RETURNING webhook_deliveries.id, webhook_deliveries.payload,
+            webhook_deliveries.attempts, webhooks.id AS webhook, webhooks.url,
+            webhooks.secret
+        "#
+    )
+    .fetch_all(&ctx.postgres)
+    .await?;
+
+    let keys = ctx.keyring();
+
+    for delivery in due {
+        let secret = decrypt_bytes_aad::<Vec<u8>>(&delivery.secret, &aad(delivery.webhook), &keys)
+            .map_err(|e| e.to_string());
+
+        let res = match secret {
+            Ok(secret) => {
+                attempt(
+                    &delivery.url,
+                    &secret,
+                    delivery.id,
+                    delivery.payload,
+                    ctx.config.webhooks.webhook_allow_private,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let attempts = delivery.attempts + 1;
+        let (status, error) = match &res {
+            Ok(status) => (Some(i32::from(status.as_u16())), None),
+            Err(e) => (None, Some(e.clone())),
+        };
+        let delivered = matches!(res, Ok(status) if status.is_success());
+        let next_attempt_at = if delivered || attempts >= MAX_ATTEMPTS {
+            None
+        } else {
+            Some(Utc::now() + backoff(attempts))
+        };
+
+        if !delivered {
+            warn!(delivery = %delivery.id, attempts, ?status, ?error, "delivery failed");
+        }
+
+        sqlx::query!(
+            r#"
+              UPDATE webhook_deliveries
+              SET
+                attempts = $2,
+                next_attempt_at = $3,
+                delivered_at = CASE WHEN $4 THEN NOW() END,
+                last_status = $5,
+                last_error = $6
+              WHERE id = $1
+            "#,
+            delivery.id,
+            attempts,
+            next_attempt_at,
+            delivered,
+            status,
+            error,
+        )
+        .execute(&ctx.postgres)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Deliver forever.
+pub async fn run(ctx: AppState) {
+    let mut interval = tokio::time::interval(StdDuration::from_secs(10));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = deliver_due(&ctx).await {
+            error!(error = %e, "webhook delivery failed");
+        }
+    }
+}
+
+/// Encrypt webhook secrets again if they aren't encrypted with the active
+/// key. Returns the number of webhooks that were updated.
+pub async fn reencrypt_secrets(ctx: &AppState) -> Result<usize> {
+    let keys = ctx.keyring();
+    let mut tx = ctx.postgres.begin().await?;
+    let mut updated = 0;
+
+    let webhooks = sqlx::query!("SELECT id, secret FROM webhooks FOR UPDATE")
+        .fetch_all(&mut tx)
+        .await?;
+
+    for webhook in webhooks {
+        let secret = match reencrypt_bytes(&webhook.secret, &aad(webhook.id), &keys) {
+            Ok(Some(secret)) => secret,
+            Ok(None) => continue,
+            Err(e) => {
+                error!(webhook = %webhook.id, error = %e, "failed to reencrypt");
+                continue;
+            }
+        };
+
+        sqlx::query!(
+            "UPDATE webhooks SET secret = $2 WHERE id = $1",
+            webhook.id,
+            secret
+        )
+        .execute(&mut tx)
+        .await?;
+
+        updated += 1;
+    }
+
+    tx.commit().await?;
+
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use chrono::{TimeZone, Utc};
+    use skool_agenda::{Change, Lesson};
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    use super::{
+        check_url, client, send, sign, Event, UrlError, DELIVERY_HEADER, SIGNATURE_HEADER,
+    };
+    use crate::credentials::Slot;
+
+    #[test]
+    fn event_payload() {
+        let lesson = Lesson {
+            teachers: Vec::new(),
+            locations: Vec::new(),
+            group: None,
+            start: Utc.with_ymd_and_hms(2023, 1, 9, 8, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 9, 9, 0, 0).unwrap(),
+            course: Some("Fysik".to_owned()),
+            id: Uuid::nil(),
+            series: None,
+            color: None,
+            source: None,
+        };
+        let slot = Slot::try_from("anna".to_owned()).unwrap();
+        let event = Event {
+            id: Uuid::nil(),
+            kind: "schedule.changed",
+            created_at: Utc.with_ymd_and_hms(2023, 1, 8, 12, 0, 0).unwrap(),
+            slot: &slot,
+            year: 2023,
+            week: 2,
+            changes: &[Change::Removed { lesson: &lesson }],
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "schedule.changed");
+        assert_eq!(json["slot"], "anna");
+        assert_eq!(json["week"], 2);
+        assert_eq!(json["changes"].as_array().unwrap().len(), 1);
+    }
+
+    type Received = mpsc::UnboundedSender<(HeaderMap, Bytes)>;
+
+    #[tokio::test]
+    async fn local_receiver() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/ok",
+                post(
+                    |State(tx): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                        tx.send((headers, body)).unwrap();
+                        StatusCode::NO_CONTENT
+                    },
+                ),
+            )
+            .route("/fail", post(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+            .with_state(tx);
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let secret = b"secret";
+        let delivery = Uuid::new_v4();
+        let body = r#"{"type":"schedule.changed"}"#;
+
+        let (url, addrs) = check_url(&format!("http://{addr}/ok"), true).await.unwrap();
+        let status = send(
+            &client(&url, &addrs),
+            url.as_str(),
+            secret,
+            delivery,
+            body.to_owned(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (headers, received) = rx.recv().await.unwrap();
+        assert_eq!(received, body.as_bytes());
+        assert_eq!(headers[DELIVERY_HEADER], delivery.to_string().as_str());
+
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+        let timestamp = signature
+            .strip_prefix("t=")
+            .and_then(|s| s.split(',').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(signature, sign(secret, timestamp, &received));
+
+        let status = send(
+            &client(&url, &addrs),
+            &format!("http://{addr}/fail"),
+            secret,
+            delivery,
+            body.to_owned(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn private_addresses() {
+        for url in [
+            "http://127.0.0.1:6379/",
+            "http://localhost:8000/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.1/",
+            "http://192.168.1.1/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "http://192.0.0.170/",
+            "http://198.18.0.1/",
+            "http://240.0.0.1/",
+            "http://[::10.0.0.1]/",
+            "http://[::]/",
+            "http://[64:ff9b::a9fe:a9fe]/",
+            "http://[64:ff9b:1::1]/",
+            "http://[2002:c0a8:101::1]/",
+        ] {
+            assert!(
+                matches!(check_url(url, false).await, Err(UrlError::NotPublic(_))),
+                "{url} was allowed"
+            );
+        }
+
+        assert!(check_url("http://127.0.0.1:6379/", true).await.is_ok());
+        assert!(check_url("http://93.184.216.34/", false).await.is_ok());
+        assert!(check_url("http://[2606:2800:220:1::]/", false)
+            .await
+            .is_ok());
+        // embedded public addresses are fine
+        assert!(check_url("http://[64:ff9b::5db8:d822]/", false)
+            .await
+            .is_ok());
+        assert!(check_url("http://[2002:5db8:d822::1]/", false)
+            .await
+            .is_ok());
+        assert!(matches!(
+            check_url("ftp://93.184.216.34/", false).await,
+            Err(UrlError::Invalid)
+        ));
+        assert!(matches!(
+            check_url("not a url", false).await,
+            Err(UrlError::Invalid)
+        ));
+    }
+}